@@ -1,3 +1,6 @@
+mod ksyms;
+
+use ksyms::embed_symbol_table;
 use std::path::PathBuf;
 
 fn main() {
@@ -8,11 +11,25 @@ fn main() {
     // https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    // give the kernel its own symbol table for backtraces
+    let kernel_with_symbols = out_dir.join("kernel");
+    let omitted = embed_symbol_table(&kernel, &kernel_with_symbols);
+    if omitted > 0 {
+        println!("cargo:warning=kernel symbol table is full, {omitted} symbols omitted");
+    }
+
     // create a BIOS disk image
     let bios_path = out_dir.join("bios.img");
-    bootloader::BiosBoot::new(&kernel)
-        .create_disk_image(&bios_path)
-        .unwrap();
+    let mut bios = bootloader::BiosBoot::new(&kernel_with_symbols);
+
+    // an optional PSF2 font is passed in the ramdisk, the kernel uses it for the console
+    println!("cargo:rerun-if-env-changed=COSMOS_FONT");
+    if let Some(font) = std::env::var_os("COSMOS_FONT").map(PathBuf::from) {
+        println!("cargo:rerun-if-changed={}", font.display());
+        bios.set_ramdisk(&font);
+    }
+
+    bios.create_disk_image(&bios_path).unwrap();
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
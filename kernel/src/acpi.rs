@@ -0,0 +1,207 @@
+//! Parsing of the ACPI tables describing the processors and interrupt controllers.
+//!
+//! The tables are read through the physical memory mapping. Only the Multiple APIC Description
+//! Table (MADT) is parsed, it lists the local APICs of all processors and the I/O APICs.
+
+use crate::memory::MEMORY_MANAGER;
+use alloc::vec::Vec;
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: [u8; 4] = *b"APIC";
+const SDT_HEADER_SIZE: u64 = 36;
+
+static MADT: Once<Madt> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The root system description pointer has an invalid signature or checksum
+    InvalidRsdp,
+    /// The table with the signature has an invalid checksum
+    InvalidChecksum([u8; 4]),
+    /// There is no table with the signature
+    TableNotFound([u8; 4]),
+}
+
+/// The processors and interrupt controllers of the system.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    /// Whether the processor can be started, disabled processors must not be used
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// First global system interrupt handled by the I/O APIC
+    pub gsi_base: u32,
+}
+
+/// An ISA interrupt which is not identity mapped to a global system interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    /// Polarity and trigger mode
+    pub flags: u16,
+}
+
+/// Parses the MADT found through the root system description pointer at `rsdp`.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized.
+pub fn init(rsdp: PhysAddr) -> Result<&'static Madt, AcpiError> {
+    if let Some(madt) = MADT.get() {
+        return Ok(madt);
+    }
+    let memory = PhysicalMemory::new();
+    let madt = memory.find_table(rsdp, MADT_SIGNATURE)?;
+    Ok(MADT.call_once(|| memory.parse_madt(madt)))
+}
+
+/// Returns the MADT parsed by [`init`].
+pub fn madt() -> Option<&'static Madt> {
+    MADT.get()
+}
+
+/// Reads the ACPI tables through the physical memory mapping.
+struct PhysicalMemory {
+    offset: VirtAddr,
+}
+
+impl PhysicalMemory {
+    fn new() -> Self {
+        Self {
+            offset: MEMORY_MANAGER
+                .get()
+                .unwrap()
+                .lock()
+                .physical_memory_offset(),
+        }
+    }
+
+    fn read<T: Copy>(&self, addr: u64) -> T {
+        // # Safety
+        // the firmware places the tables in memory which is never handed out by the frame
+        // allocator
+        unsafe { (self.offset + addr).as_ptr::<T>().read_unaligned() }
+    }
+
+    fn bytes(&self, addr: u64, len: u64) -> &[u8] {
+        // # Safety
+        // see `read`
+        unsafe { core::slice::from_raw_parts((self.offset + addr).as_ptr(), len as usize) }
+    }
+
+    fn checksum(&self, addr: u64, len: u64) -> bool {
+        self.bytes(addr, len)
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            == 0
+    }
+
+    /// Returns the address of the table with `signature`.
+    fn find_table(&self, rsdp: PhysAddr, signature: [u8; 4]) -> Result<u64, AcpiError> {
+        let rsdp = rsdp.as_u64();
+        if self.bytes(rsdp, 8) != RSDP_SIGNATURE || !self.checksum(rsdp, 20) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+
+        // ACPI 2.0 replaces the root table by one with 64 bit addresses
+        let revision: u8 = self.read(rsdp + 15);
+        let (root, entry_size) = if revision >= 2 && self.checksum(rsdp, 36) {
+            (self.read::<u64>(rsdp + 24), 8)
+        } else {
+            (u64::from(self.read::<u32>(rsdp + 16)), 4)
+        };
+        let root_length = u64::from(self.read::<u32>(root + 4));
+        if !self.checksum(root, root_length) {
+            let root_signature = self.read(root);
+            return Err(AcpiError::InvalidChecksum(root_signature));
+        }
+
+        let entries = (root_length.saturating_sub(SDT_HEADER_SIZE)) / entry_size;
+        for index in 0..entries {
+            let entry = root + SDT_HEADER_SIZE + index * entry_size;
+            let table = match entry_size {
+                8 => self.read::<u64>(entry),
+                _ => u64::from(self.read::<u32>(entry)),
+            };
+            if self.read::<[u8; 4]>(table) != signature {
+                continue;
+            }
+            let length = u64::from(self.read::<u32>(table + 4));
+            if !self.checksum(table, length) {
+                return Err(AcpiError::InvalidChecksum(signature));
+            }
+            return Ok(table);
+        }
+        Err(AcpiError::TableNotFound(signature))
+    }
+
+    fn parse_madt(&self, table: u64) -> Madt {
+        const PROCESSOR_LOCAL_APIC: u8 = 0;
+        const IO_APIC: u8 = 1;
+        const INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+        const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+        const PROCESSOR_LOCAL_X2APIC: u8 = 9;
+        const ENABLED: u32 = 1;
+
+        let length = u64::from(self.read::<u32>(table + 4));
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(u64::from(self.read::<u32>(table + SDT_HEADER_SIZE))),
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        let mut entry = table + SDT_HEADER_SIZE + 8;
+        while entry + 2 <= table + length {
+            let kind: u8 = self.read(entry);
+            let entry_length = u64::from(self.read::<u8>(entry + 1));
+            if entry_length < 2 {
+                break;
+            }
+            match kind {
+                PROCESSOR_LOCAL_APIC => madt.processors.push(Processor {
+                    processor_id: u32::from(self.read::<u8>(entry + 2)),
+                    apic_id: u32::from(self.read::<u8>(entry + 3)),
+                    enabled: self.read::<u32>(entry + 4) & ENABLED != 0,
+                }),
+                IO_APIC => madt.io_apics.push(IoApic {
+                    id: self.read(entry + 2),
+                    address: PhysAddr::new(u64::from(self.read::<u32>(entry + 4))),
+                    gsi_base: self.read(entry + 8),
+                }),
+                INTERRUPT_SOURCE_OVERRIDE => madt.overrides.push(InterruptOverride {
+                    source: self.read(entry + 3),
+                    gsi: self.read(entry + 4),
+                    flags: self.read(entry + 8),
+                }),
+                LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    madt.local_apic_address = PhysAddr::new(self.read(entry + 4));
+                }
+                PROCESSOR_LOCAL_X2APIC => madt.processors.push(Processor {
+                    processor_id: self.read(entry + 12),
+                    apic_id: self.read(entry + 4),
+                    enabled: self.read::<u32>(entry + 8) & ENABLED != 0,
+                }),
+                _ => {}
+            }
+            entry += entry_length;
+        }
+        madt
+    }
+}
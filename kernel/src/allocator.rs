@@ -0,0 +1,114 @@
+pub mod reserve;
+pub mod slab;
+
+use crate::memory::MEMORY_MANAGER;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::LockedHeap;
+use slab::{PageSource, SlabCache};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+pub const HEAP_START: u64 = 0x4444_4444_0000;
+pub const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    heap: LockedHeap::empty(),
+    size_classes: [
+        size_class("kmalloc-8", 8),
+        size_class("kmalloc-16", 16),
+        size_class("kmalloc-32", 32),
+        size_class("kmalloc-64", 64),
+        size_class("kmalloc-128", 128),
+        size_class("kmalloc-256", 256),
+        size_class("kmalloc-512", 512),
+        size_class("kmalloc-1024", 1024),
+    ],
+};
+
+/// Serves small allocations from slab caches of power of two sizes and everything else from the
+/// linked list heap. The slabs are frames of the [`reserve`].
+struct KernelAllocator {
+    heap: LockedHeap,
+    size_classes: [Mutex<SlabCache>; 8],
+}
+
+impl KernelAllocator {
+    /// Returns the smallest size class fitting the size and alignment of `layout`.
+    fn size_class(&self, layout: Layout) -> Option<&Mutex<SlabCache>> {
+        let size = layout.size().max(layout.align()).next_power_of_two();
+        let index = size.trailing_zeros().saturating_sub(3) as usize;
+        self.size_classes.get(index)
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocate = || match self.size_class(layout) {
+            Some(cache) => cache
+                .lock()
+                .allocate()
+                .map_or(ptr::null_mut(), NonNull::as_ptr),
+            None => self.heap.alloc(layout),
+        };
+        let mut ptr = allocate();
+        // free the memory held by empty slabs and try again
+        if ptr.is_null() && slab::reclaim() > 0 {
+            ptr = allocate();
+        }
+        // the caches are unlocked again, so the memory manager may be used
+        if self.size_class(layout).is_some() {
+            reserve::balance();
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.size_class(layout) {
+            Some(cache) => cache.lock().deallocate(NonNull::new_unchecked(ptr)),
+            None => self.heap.dealloc(ptr, layout),
+        }
+    }
+}
+
+const fn size_class(name: &'static str, size: usize) -> Mutex<SlabCache> {
+    // # Safety
+    // the sizes are powers of two
+    let layout = unsafe { Layout::from_size_align_unchecked(size, size) };
+    Mutex::new(SlabCache::new(name, layout, None, PageSource::Reserve))
+}
+
+/// Maps the kernel heap region and hands it over to the global allocator.
+///
+/// The heap is mapped up front instead of on demand, as the memory areas resolving page faults
+/// are themselves stored on the heap.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized.
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    MEMORY_MANAGER
+        .get()
+        .unwrap()
+        .lock()
+        .allocate_frames_for_memory_region(
+            VirtAddr::new(HEAP_START),
+            HEAP_SIZE,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )?;
+
+    // # Safety
+    // The heap region has just been mapped and is not used by anything else
+    unsafe {
+        ALLOCATOR.heap.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+    }
+
+    for cache in &ALLOCATOR.size_classes {
+        slab::register(cache);
+    }
+    reserve::balance();
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Reserve of free frames for the slabs of the global allocator.
+//!
+//! The memory manager allocates from the heap while it is locked, so the size classes of the
+//! global allocator cannot take frames from it while they grow. They take them from this reserve
+//! instead. [`balance`] refills the reserve from the memory manager and returns surplus frames to
+//! it, it is called by the global allocator after it has unlocked its caches and only uses the
+//! memory manager if it is free.
+
+use crate::memory::MEMORY_MANAGER;
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::PhysAddr;
+
+/// The reserve is refilled when it holds fewer frames.
+const LOW_WATERMARK: usize = 16;
+/// The frames beyond this are returned to the memory manager.
+const HIGH_WATERMARK: usize = 64;
+/// Number of frames left in the reserve by [`balance`].
+const TARGET: usize = 32;
+
+static RESERVE: Mutex<Reserve> = Mutex::new(Reserve {
+    head: ptr::null_mut(),
+    len: 0,
+});
+
+/// Free frames linked through their first word, accessed through the physical memory mapping.
+struct Reserve {
+    head: *mut FreeFrame,
+    len: usize,
+}
+
+// # Safety
+// The frames in the list are owned by the reserve and only accessed through it
+unsafe impl Send for Reserve {}
+
+struct FreeFrame {
+    next: *mut FreeFrame,
+}
+
+/// Takes a frame from the reserve and returns its address in the physical memory mapping.
+pub(super) fn take() -> Option<NonNull<u8>> {
+    let mut reserve = RESERVE.lock();
+    let frame = NonNull::new(reserve.head)?;
+    // # Safety
+    // the frames in the list start with a `FreeFrame`
+    reserve.head = unsafe { frame.as_ref().next };
+    reserve.len -= 1;
+    Some(frame.cast())
+}
+
+/// Puts a frame back into the reserve.
+///
+/// # Safety
+/// `page` must have been returned by [`take`] and must not be used afterwards.
+pub(super) unsafe fn give(page: NonNull<u8>) {
+    let frame = page.cast::<FreeFrame>();
+    let mut reserve = RESERVE.lock();
+    frame.as_ptr().write(FreeFrame { next: reserve.head });
+    reserve.head = frame.as_ptr();
+    reserve.len += 1;
+}
+
+/// Returns the number of frames in the reserve.
+pub fn len() -> usize {
+    RESERVE.lock().len
+}
+
+/// Refills the reserve when it holds fewer than [`LOW_WATERMARK`] frames and returns the frames
+/// beyond [`HIGH_WATERMARK`] to the memory manager.
+///
+/// Nothing happens while the memory manager is locked. Must not be called while a slab cache of
+/// the global allocator is locked, the memory manager may wait for it.
+pub(super) fn balance() {
+    let frames = len();
+    if (LOW_WATERMARK..=HIGH_WATERMARK).contains(&frames) {
+        return;
+    }
+    let Some(mut manager) = MEMORY_MANAGER.get().and_then(|manager| manager.try_lock()) else {
+        return;
+    };
+    let offset = manager.physical_memory_offset();
+
+    if frames < LOW_WATERMARK {
+        for _ in frames..TARGET {
+            let Some(frame) = manager.allocate_frame() else {
+                break;
+            };
+            let page = (offset + frame.start_address().as_u64()).as_mut_ptr();
+            // # Safety
+            // the frame has just been allocated and is only accessed through the reserve
+            unsafe { give(NonNull::new_unchecked(page)) };
+        }
+    } else {
+        while len() > TARGET {
+            let Some(page) = take() else {
+                break;
+            };
+            let address = PhysAddr::new(page.as_ptr() as u64 - offset.as_u64());
+            // # Safety
+            // the frame has been taken out of the reserve and is not used anymore
+            unsafe { manager.deallocate_frame(PhysFrame::containing_address(address)) };
+        }
+    }
+}
@@ -0,0 +1,428 @@
+//! Slab allocator for fixed-size objects.
+//!
+//! A [`SlabCache`] hands out objects of a single layout from slabs, pages split into equally
+//! sized objects. Each slab starts with a header tracking its free objects in a bitmap, so the
+//! cache never writes to the objects themselves. The constructor of a cache runs once for every
+//! object when its slab is created and objects must be returned to the cache in their
+//! constructed state.
+//!
+//! Caches registered with [`register`] are reported by [`stats`] and their empty slabs are
+//! returned to the system by [`reclaim`], which the global allocator calls when it runs out of
+//! memory.
+
+use super::{reserve, ALLOCATOR, HEAP_SIZE, HEAP_START};
+use crate::memory::MEMORY_MANAGER;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::PhysAddr;
+
+const SLAB_SIZE: usize = 4096;
+const MAX_OBJECTS: usize = 512;
+const MIN_OBJECT_SIZE: usize = SLAB_SIZE / MAX_OBJECTS;
+
+/// Maximum number of caches registered with [`register`].
+const MAX_CACHES: usize = 32;
+
+/// The registered caches, a fixed array as registering must not allocate
+static CACHES: Mutex<[Option<&'static Mutex<SlabCache>>; MAX_CACHES]> =
+    Mutex::new([None; MAX_CACHES]);
+
+/// Where the pages of the slabs come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSource {
+    /// Frames of the frame allocator, accessed through the physical memory mapping
+    Frames,
+    /// Frames of the [`reserve`] of the global allocator, which does not lock the memory manager.
+    /// Pages of the kernel heap are used while the reserve is empty.
+    Reserve,
+    /// Pages of the kernel heap
+    Heap,
+}
+
+impl PageSource {
+    fn allocate(self) -> Option<NonNull<SlabHeader>> {
+        let page = match self {
+            PageSource::Frames => {
+                let mut manager = MEMORY_MANAGER.get()?.lock();
+                let frame = manager.allocate_frame()?;
+                (manager.physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+            }
+            PageSource::Reserve => match reserve::take() {
+                Some(page) => page.as_ptr(),
+                None => PageSource::Heap.allocate()?.as_ptr().cast(),
+            },
+            PageSource::Heap => ALLOCATOR
+                .heap
+                .lock()
+                .allocate_first_fit(slab_layout())
+                .ok()?
+                .as_ptr(),
+        };
+        NonNull::new(page.cast())
+    }
+
+    /// # Safety
+    /// `slab` must have been allocated from this source and must not be used afterwards.
+    unsafe fn deallocate(self, slab: NonNull<SlabHeader>) {
+        match self {
+            PageSource::Frames => {
+                let mut manager = MEMORY_MANAGER.get().unwrap().lock();
+                let physical_address =
+                    slab.as_ptr() as u64 - manager.physical_memory_offset().as_u64();
+                manager.deallocate_frame(PhysFrame::containing_address(PhysAddr::new(
+                    physical_address,
+                )));
+            }
+            PageSource::Reserve if !is_heap_page(slab) => reserve::give(slab.cast()),
+            PageSource::Reserve | PageSource::Heap => {
+                ALLOCATOR.heap.lock().deallocate(slab.cast(), slab_layout());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    /// Size of the objects including padding
+    pub object_size: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    /// Number of objects which fit into the allocated slabs
+    pub capacity: usize,
+    pub allocations: u64,
+    pub frees: u64,
+    /// Number of empty slabs returned to the page source
+    pub reclaimed_slabs: u64,
+}
+
+#[repr(C)]
+struct SlabHeader {
+    next: *mut SlabHeader,
+    prev: *mut SlabHeader,
+    /// Set bits mark free objects
+    free: [u64; MAX_OBJECTS / 64],
+    in_use: usize,
+}
+
+/// Doubly linked list of slabs.
+struct SlabList {
+    head: *mut SlabHeader,
+}
+
+impl SlabList {
+    const EMPTY: SlabList = SlabList {
+        head: ptr::null_mut(),
+    };
+
+    /// # Safety
+    /// `slab` must be a valid slab which is not in any list.
+    unsafe fn push(&mut self, slab: *mut SlabHeader) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = self.head;
+        if let Some(head) = self.head.as_mut() {
+            head.prev = slab;
+        }
+        self.head = slab;
+    }
+
+    /// # Safety
+    /// `slab` must be a valid slab in this list.
+    unsafe fn remove(&mut self, slab: *mut SlabHeader) {
+        let (prev, next) = ((*slab).prev, (*slab).next);
+        match prev.as_mut() {
+            Some(prev) => prev.next = next,
+            None => self.head = next,
+        }
+        if let Some(next) = next.as_mut() {
+            next.prev = prev;
+        }
+    }
+}
+
+/// A named cache of objects with the same layout.
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    /// Offset of the first object from the start of a slab
+    objects_offset: usize,
+    objects_per_slab: usize,
+    constructor: Option<fn(NonNull<u8>)>,
+    source: PageSource,
+    partial: SlabList,
+    full: SlabList,
+    empty: SlabList,
+    slabs: usize,
+    objects_in_use: usize,
+    allocations: u64,
+    frees: u64,
+    reclaimed_slabs: u64,
+}
+
+// # Safety
+// The slabs are owned by the cache and only accessed through it
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    /// Creates an empty cache for objects of `layout`.
+    ///
+    /// # Panics
+    /// The function will panic if not even a single object fits into a slab.
+    pub const fn new(
+        name: &'static str,
+        layout: Layout,
+        constructor: Option<fn(NonNull<u8>)>,
+        source: PageSource,
+    ) -> Self {
+        let align = layout.align();
+        let size = if layout.size() < MIN_OBJECT_SIZE {
+            MIN_OBJECT_SIZE
+        } else {
+            layout.size()
+        };
+        let object_size = round_up(size, align);
+        let objects_offset = round_up(core::mem::size_of::<SlabHeader>(), align);
+        assert!(
+            objects_offset + object_size <= SLAB_SIZE,
+            "object does not fit into a slab"
+        );
+        let objects_per_slab = (SLAB_SIZE - objects_offset) / object_size;
+
+        Self {
+            name,
+            object_size,
+            objects_offset,
+            objects_per_slab: if objects_per_slab > MAX_OBJECTS {
+                MAX_OBJECTS
+            } else {
+                objects_per_slab
+            },
+            constructor,
+            source,
+            partial: SlabList::EMPTY,
+            full: SlabList::EMPTY,
+            empty: SlabList::EMPTY,
+            slabs: 0,
+            objects_in_use: 0,
+            allocations: 0,
+            frees: 0,
+            reclaimed_slabs: 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Allocates an object, creating a new slab if all slabs are full.
+    ///
+    /// Returns [`None`] if the page source is out of memory.
+    pub fn allocate(&mut self) -> Option<NonNull<u8>> {
+        let slab = if !self.partial.head.is_null() {
+            self.partial.head
+        } else if !self.empty.head.is_null() {
+            self.empty.head
+        } else {
+            self.grow()?
+        };
+
+        // # Safety
+        // the slab belongs to the cache and has a free object as it is not full
+        unsafe {
+            let header = &mut *slab;
+            let (word, bits) = header
+                .free
+                .iter_mut()
+                .enumerate()
+                .find(|(_, bits)| **bits != 0)?;
+            let index = word * 64 + bits.trailing_zeros() as usize;
+            *bits &= *bits - 1;
+
+            self.move_slab(slab, header.in_use, header.in_use + 1);
+            header.in_use += 1;
+            self.objects_in_use += 1;
+            self.allocations += 1;
+            NonNull::new(self.object(slab, index))
+        }
+    }
+
+    /// Returns an object to the cache.
+    ///
+    /// # Safety
+    /// `object` must have been allocated from this cache and must not be used afterwards.
+    ///
+    /// # Panics
+    /// The function will panic if the object is already free.
+    pub unsafe fn deallocate(&mut self, object: NonNull<u8>) {
+        let address = object.as_ptr() as usize;
+        let slab = (address & !(SLAB_SIZE - 1)) as *mut SlabHeader;
+        let index = (address - slab as usize - self.objects_offset) / self.object_size;
+
+        let header = &mut *slab;
+        let (word, bit) = (index / 64, 1 << (index % 64));
+        assert!(
+            header.free[word] & bit == 0,
+            "double free of {address:#x} in cache {}",
+            self.name
+        );
+        header.free[word] |= bit;
+
+        self.move_slab(slab, header.in_use, header.in_use - 1);
+        header.in_use -= 1;
+        self.objects_in_use -= 1;
+        self.frees += 1;
+    }
+
+    /// Returns all empty slabs to the page source and the number of freed slabs.
+    pub fn reclaim(&mut self) -> usize {
+        let mut reclaimed = 0;
+        while let Some(slab) = NonNull::new(self.empty.head) {
+            // # Safety
+            // the slab is empty, so none of its objects are in use
+            unsafe {
+                self.empty.remove(slab.as_ptr());
+                self.source.deallocate(slab);
+            }
+            reclaimed += 1;
+        }
+        self.slabs -= reclaimed;
+        self.reclaimed_slabs += reclaimed as u64;
+        reclaimed
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name,
+            object_size: self.object_size,
+            slabs: self.slabs,
+            objects_in_use: self.objects_in_use,
+            capacity: self.slabs * self.objects_per_slab,
+            allocations: self.allocations,
+            frees: self.frees,
+            reclaimed_slabs: self.reclaimed_slabs,
+        }
+    }
+
+    /// Allocates an empty slab and constructs its objects.
+    fn grow(&mut self) -> Option<*mut SlabHeader> {
+        let slab = self.source.allocate()?.as_ptr();
+        // # Safety
+        // the page has just been allocated for the slab
+        unsafe {
+            let mut free = [0; MAX_OBJECTS / 64];
+            for index in 0..self.objects_per_slab {
+                free[index / 64] |= 1 << (index % 64);
+            }
+            slab.write(SlabHeader {
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                free,
+                in_use: 0,
+            });
+            if let Some(constructor) = self.constructor {
+                for index in 0..self.objects_per_slab {
+                    constructor(NonNull::new_unchecked(self.object(slab, index)));
+                }
+            }
+            self.empty.push(slab);
+        }
+        self.slabs += 1;
+        Some(slab)
+    }
+
+    /// Moves `slab` to the list matching its number of objects in use.
+    ///
+    /// # Safety
+    /// `slab` must belong to the cache and have `before` objects in use.
+    unsafe fn move_slab(&mut self, slab: *mut SlabHeader, before: usize, after: usize) {
+        let (from, to) = (self.list(before), self.list(after));
+        if from != to {
+            self.list_mut(from).remove(slab);
+            self.list_mut(to).push(slab);
+        }
+    }
+
+    fn list(&self, in_use: usize) -> SlabState {
+        if in_use == 0 {
+            SlabState::Empty
+        } else if in_use == self.objects_per_slab {
+            SlabState::Full
+        } else {
+            SlabState::Partial
+        }
+    }
+
+    fn list_mut(&mut self, state: SlabState) -> &mut SlabList {
+        match state {
+            SlabState::Empty => &mut self.empty,
+            SlabState::Partial => &mut self.partial,
+            SlabState::Full => &mut self.full,
+        }
+    }
+
+    fn object(&self, slab: *mut SlabHeader, index: usize) -> *mut u8 {
+        slab.cast::<u8>()
+            .wrapping_add(self.objects_offset + index * self.object_size)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlabState {
+    Empty,
+    Partial,
+    Full,
+}
+
+/// Registers `cache` for [`stats`] and [`reclaim`].
+///
+/// # Panics
+/// The function will panic if [`MAX_CACHES`] caches are already registered.
+pub fn register(cache: &'static Mutex<SlabCache>) {
+    let mut caches = CACHES.lock();
+    let slot = caches
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many slab caches");
+    *slot = Some(cache);
+}
+
+/// Returns the statistics of all registered caches.
+pub fn stats() -> Vec<CacheStats> {
+    let caches = *CACHES.lock();
+    caches
+        .iter()
+        .flatten()
+        .map(|cache| cache.lock().stats())
+        .collect()
+}
+
+/// Returns the empty slabs of all registered caches to their page sources and the number of
+/// freed slabs.
+///
+/// Caches which are in use are skipped, so this can be called while allocating.
+pub fn reclaim() -> usize {
+    let Some(caches) = CACHES.try_lock() else {
+        return 0;
+    };
+    caches
+        .iter()
+        .flatten()
+        .filter_map(|cache| cache.try_lock())
+        .map(|mut cache| cache.reclaim())
+        .sum()
+}
+
+const fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn is_heap_page(page: NonNull<SlabHeader>) -> bool {
+    (HEAP_START..HEAP_START + HEAP_SIZE as u64).contains(&(page.as_ptr() as u64))
+}
+
+fn slab_layout() -> Layout {
+    Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+}
@@ -0,0 +1,193 @@
+//! Frame pointer based stack traces.
+//!
+//! The kernel is built with frame pointers (see `.cargo/config.toml`), so every stack frame starts
+//! with the caller's `rbp` followed by the return address. Addresses are resolved with the symbol
+//! table which the build script writes into the [`KERNEL_SYMBOLS`] section of the kernel
+//! executable, the integration test runner embeds it into the test kernels as well. Kernels
+//! without it print raw addresses.
+//!
+//! # Symbol table format
+//! All values are little endian.
+//! ```text
+//! magic: [u8; 4] = "KSYM"
+//! count: u32
+//! base: u64                   link address of the table itself
+//! entries: [Entry; count]     sorted by address
+//! names: [u8]                 UTF-8 names referenced by the entries
+//!
+//! Entry { address: u64, size: u32, name_offset: u32, name_len: u32 }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::fmt;
+
+/// Size reserved for the symbol table, it must match `SYMBOL_TABLE_SIZE` in `build.rs`.
+pub const SYMBOL_TABLE_SIZE: usize = 512 * 1024;
+
+/// Maximum number of frames captured in a [`Backtrace`].
+const MAX_FRAMES: usize = 64;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 20;
+
+/// Storage for the symbol table, filled in after linking.
+#[repr(transparent)]
+struct SymbolTableStorage(UnsafeCell<[u8; SYMBOL_TABLE_SIZE]>);
+
+// # Safety
+// The table is written only by the build script, the kernel only reads it
+unsafe impl Sync for SymbolTableStorage {}
+
+// the interior mutability keeps the compiler from assuming the table contains only zeros
+#[used]
+#[link_section = ".ksyms"]
+static KERNEL_SYMBOLS: SymbolTableStorage =
+    SymbolTableStorage(UnsafeCell::new([0; SYMBOL_TABLE_SIZE]));
+
+/// A function symbol of the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// Runtime address of the function
+    pub address: u64,
+    pub size: u64,
+}
+
+/// Returns the function containing `address`.
+pub fn resolve(address: u64) -> Option<Symbol> {
+    // # Safety
+    // The table is never written at runtime
+    let table: &'static [u8; SYMBOL_TABLE_SIZE] = unsafe { &*KERNEL_SYMBOLS.0.get() };
+    if &table[..4] != MAGIC {
+        return None;
+    }
+    let count = read_u32(table, 4) as usize;
+    if HEADER_SIZE + count * ENTRY_SIZE > SYMBOL_TABLE_SIZE {
+        return None;
+    }
+    let base = read_u64(table, 8);
+    // the kernel is relocated by the bootloader, symbols hold link addresses
+    let load_bias = (table.as_ptr() as u64).wrapping_sub(base);
+    let linked_address = address.wrapping_sub(load_bias);
+
+    let entry = |index: usize| {
+        let offset = HEADER_SIZE + index * ENTRY_SIZE;
+        (
+            read_u64(table, offset),
+            u64::from(read_u32(table, offset + 8)),
+            read_u32(table, offset + 12) as usize,
+            read_u32(table, offset + 16) as usize,
+        )
+    };
+
+    // find the last symbol starting at or before the address
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = (low + high) / 2;
+        if entry(middle).0 <= linked_address {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    let (start, size, name_offset, name_len) = entry(low.checked_sub(1)?);
+    if linked_address >= start + size.max(1) {
+        return None;
+    }
+
+    let names = HEADER_SIZE + count * ENTRY_SIZE;
+    let name = table.get(names + name_offset..names + name_offset + name_len)?;
+    Some(Symbol {
+        name: core::str::from_utf8(name).ok()?,
+        address: start.wrapping_add(load_bias),
+        size,
+    })
+}
+
+/// Return addresses of the call stack, innermost first.
+#[derive(Debug)]
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Captures the call stack of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let frame_pointer: u64;
+        // # Safety
+        // reading rbp has no side effects
+        unsafe {
+            core::arch::asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack));
+        }
+        // # Safety
+        // the kernel is built with frame pointers, so rbp points to a valid frame
+        unsafe { Self::from_frame_pointer(frame_pointer) }
+    }
+
+    /// Walks the chain of frames starting at `frame_pointer`.
+    ///
+    /// The walk stops at a null, misaligned or non-canonical frame pointer and when the frame
+    /// pointers stop growing, which is the case at the bottom of the stack.
+    ///
+    /// # Safety
+    /// `frame_pointer` must be the frame pointer of a stack frame laid out with frame pointers.
+    pub unsafe fn from_frame_pointer(mut frame_pointer: u64) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+        while backtrace.len < MAX_FRAMES && is_valid_frame_pointer(frame_pointer) {
+            let frame = frame_pointer as *const u64;
+            let (caller_frame_pointer, return_address) = (*frame, *frame.add(1));
+            if return_address == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = return_address;
+            backtrace.len += 1;
+            if caller_frame_pointer <= frame_pointer {
+                break;
+            }
+            frame_pointer = caller_frame_pointer;
+        }
+        backtrace
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        for (index, &address) in self.frames().iter().enumerate() {
+            // return addresses point after the call, look up the call instruction itself
+            match resolve(address - 1) {
+                Some(symbol) => writeln!(
+                    f,
+                    "{index:4}: {address:#018x} - {}+{:#x}",
+                    symbol.name,
+                    address - symbol.address
+                )?,
+                None => writeln!(f, "{index:4}: {address:#018x} - <unknown>")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_frame_pointer(frame_pointer: u64) -> bool {
+    let canonical = matches!(frame_pointer >> 47, 0 | 0x1FFFF);
+    frame_pointer != 0 && frame_pointer % 8 == 0 && canonical
+}
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(table: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(table[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,220 @@
+//! Glyph sources for the console.
+//!
+//! Glyphs come either from the built-in noto-sans-mono-bitmap font (any of its compiled in
+//! weights and raster heights) or from a PSF2 font, e.g. one loaded from the ramdisk. Characters
+//! missing from a font are rendered with a replacement glyph instead of failing.
+
+use alloc::collections::BTreeMap;
+use noto_sans_mono_bitmap::RasterizedChar;
+
+pub use noto_sans_mono_bitmap::{FontWeight, RasterHeight};
+
+const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+#[derive(Debug)]
+pub enum Font {
+    Noto {
+        weight: FontWeight,
+        height: RasterHeight,
+    },
+    Psf2(Psf2Font<'static>),
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font::Noto {
+            weight: FontWeight::Regular,
+            height: RasterHeight::Size16,
+        }
+    }
+}
+
+impl Font {
+    pub fn glyph_width(&self) -> usize {
+        match self {
+            Font::Noto { weight, height } => {
+                noto_sans_mono_bitmap::get_raster_width(*weight, *height)
+            }
+            Font::Psf2(font) => font.width,
+        }
+    }
+
+    pub fn glyph_height(&self) -> usize {
+        match self {
+            Font::Noto { height, .. } => height.val(),
+            Font::Psf2(font) => font.height,
+        }
+    }
+
+    /// Returns the glyph for `c`.
+    ///
+    /// If the font does not contain `c`, the font's replacement character (`�`) is used, or an
+    /// empty box if the font does not have one either.
+    pub fn glyph(&self, c: char) -> Glyph<'_> {
+        match self {
+            Font::Noto { weight, height } => [c, REPLACEMENT_CHARACTER]
+                .into_iter()
+                .find_map(|c| noto_sans_mono_bitmap::get_raster(c, *weight, *height))
+                .map(Glyph::Noto),
+            Font::Psf2(font) => [c, REPLACEMENT_CHARACTER]
+                .into_iter()
+                .find_map(|c| font.bitmap(c))
+                .map(|bitmap| Glyph::Psf2 {
+                    bitmap,
+                    bytes_per_row: font.bytes_per_row(),
+                }),
+        }
+        .unwrap_or(Glyph::Box {
+            width: self.glyph_width(),
+            height: self.glyph_height(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Glyph<'a> {
+    Noto(RasterizedChar),
+    Psf2 {
+        bitmap: &'a [u8],
+        bytes_per_row: usize,
+    },
+    /// Outline of the glyph cell, used when no glyph is available
+    Box {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Glyph<'_> {
+    /// Returns the intensity of the pixel from 0 (background) to 255 (foreground).
+    pub fn intensity(&self, x: usize, y: usize) -> u8 {
+        match self {
+            Glyph::Noto(raster) => raster.raster()[y][x],
+            Glyph::Psf2 {
+                bitmap,
+                bytes_per_row,
+            } => {
+                let byte = bitmap[y * bytes_per_row + x / 8];
+                if byte & (0x80 >> (x % 8)) != 0 {
+                    0xFF
+                } else {
+                    0x00
+                }
+            }
+            Glyph::Box { width, height } => {
+                let border = x == 1 || y == 1 || x + 2 == *width || y + 2 == *height;
+                let inside = (1..width - 1).contains(&x) && (1..height - 1).contains(&y);
+                if border && inside {
+                    0xFF
+                } else {
+                    0x00
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Psf2Error {
+    InvalidMagic,
+    /// The data ends before the header, glyphs or unicode table
+    UnexpectedEnd,
+    InvalidHeader,
+}
+
+/// Font in the PC Screen Font version 2 format.
+#[derive(Debug)]
+pub struct Psf2Font<'a> {
+    glyphs: &'a [u8],
+    glyph_count: usize,
+    glyph_size: usize,
+    width: usize,
+    height: usize,
+    /// Maps characters to glyph indices, without a unicode table glyphs are indexed by code point
+    unicode_table: Option<BTreeMap<char, usize>>,
+}
+
+impl<'a> Psf2Font<'a> {
+    const MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+    const HAS_UNICODE_TABLE: u32 = 0x01;
+    const SEPARATOR: u8 = 0xFF;
+    const SEQUENCE_START: u8 = 0xFE;
+
+    pub fn parse(data: &'a [u8]) -> Result<Self, Psf2Error> {
+        if data.get(..4) != Some(&Self::MAGIC) {
+            return Err(Psf2Error::InvalidMagic);
+        }
+        let field = |index: usize| {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .ok_or(Psf2Error::UnexpectedEnd)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        let header_size = field(2)?;
+        let flags = field(3)? as u32;
+        let glyph_count = field(4)?;
+        let glyph_size = field(5)?;
+        let height = field(6)?;
+        let width = field(7)?;
+
+        if width == 0 || height == 0 || glyph_size < height * ((width + 7) / 8) {
+            return Err(Psf2Error::InvalidHeader);
+        }
+
+        let glyphs_end = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(Psf2Error::InvalidHeader)?;
+        let glyphs = data
+            .get(header_size..glyphs_end)
+            .ok_or(Psf2Error::UnexpectedEnd)?;
+
+        let unicode_table = (flags & Self::HAS_UNICODE_TABLE != 0)
+            .then(|| Self::parse_unicode_table(&data[glyphs_end..], glyph_count));
+
+        Ok(Self {
+            glyphs,
+            glyph_count,
+            glyph_size,
+            width,
+            height,
+            unicode_table,
+        })
+    }
+
+    /// Every glyph has an entry of UTF-8 encoded characters terminated by `0xFF`. Multi character
+    /// sequences, introduced by `0xFE`, cannot be represented in a single cell and are skipped.
+    fn parse_unicode_table(table: &[u8], glyph_count: usize) -> BTreeMap<char, usize> {
+        let mut map = BTreeMap::new();
+        let entries = table.split(|&byte| byte == Self::SEPARATOR);
+        for (index, entry) in entries.take(glyph_count).enumerate() {
+            let single_characters = entry
+                .split(|&byte| byte == Self::SEQUENCE_START)
+                .next()
+                .unwrap_or_default();
+            let Ok(characters) = core::str::from_utf8(single_characters) else {
+                continue;
+            };
+            for c in characters.chars() {
+                map.entry(c).or_insert(index);
+            }
+        }
+        map
+    }
+
+    fn bytes_per_row(&self) -> usize {
+        (self.width + 7) / 8
+    }
+
+    fn bitmap(&self, c: char) -> Option<&'a [u8]> {
+        let index = match &self.unicode_table {
+            Some(table) => *table.get(&c)?,
+            None => c as usize,
+        };
+        if index >= self.glyph_count {
+            return None;
+        }
+        self.glyphs
+            .get(index * self.glyph_size..(index + 1) * self.glyph_size)
+    }
+}
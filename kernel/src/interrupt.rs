@@ -1,10 +1,24 @@
-use crate::{print, println};
+pub mod apic;
+mod fault;
+pub mod irq;
+mod machine_check;
+pub mod nmi;
+pub mod stats;
+pub mod work;
+
+use crate::memory::{stack, tlb, user, PageFaultError, MEMORY_MANAGER};
+use crate::sync::IrqSpinLock;
+use crate::{logger, percpu, print, println, smp};
+use alloc::boxed::Box;
+use alloc::format;
 use core::fmt::Debug;
-use pc_keyboard::{HandleControl, Keyboard};
+use core::sync::atomic::{AtomicBool, Ordering};
+use fault::{exception_entry, ExceptionContext, PageFaultDescription, RegisterDump, SelectorError};
+use irq::IrqReturn;
+use pc_keyboard::{HandleControl, KeyCode, KeyState, Keyboard};
 use pic8259::ChainedPics;
 use spin::once::Once;
-use spin::Mutex;
-use x86_64::registers::control::Cr2;
+use work::Work;
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::{
     instructions,
@@ -21,14 +35,18 @@ use x86_64::{
 
 type SupportedKeyboard = Keyboard<pc_keyboard::layouts::Us104Key, pc_keyboard::ScancodeSet1>;
 
+static EARLY_IDT: Once<InterruptDescriptorTable> = Once::new();
 static IDT: Once<InterruptDescriptorTable> = Once::new();
-static TSS: Once<TaskStateSegment> = Once::new();
-static GDT: Once<GlobalDescriptorTable> = Once::new();
-static SEGMENT_SELECTORS: Once<SegmentSelectors> = Once::new();
-static PICS: Once<Mutex<ChainedPics>> = Once::new();
-static KEYBOARD: Once<Mutex<SupportedKeyboard>> = Once::new();
+static PICS: Once<IrqSpinLock<ChainedPics>> = Once::new();
+static KEYBOARD: Once<IrqSpinLock<SupportedKeyboard>> = Once::new();
+static SHIFT_PRESSED: AtomicBool = AtomicBool::new(false);
+static SCANCODES: IrqSpinLock<Scancodes> = IrqSpinLock::new(Scancodes::new());
+static KEYBOARD_WORK: Work = Work::new(process_scancodes);
 
 const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const DOUBLE_FAULT_STACK_SIZE: usize = 32 * 1024;
+const NMI_IST_INDEX: u16 = 1;
+const NMI_STACK_SIZE: usize = 16 * 1024;
 
 // hardware interrupts PICs (slots 32-47)
 // Safety - ensure that the PICs does not overlap
@@ -37,14 +55,44 @@ const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
 const PS2_CONTROLLER_PORT: u16 = 0x60;
 
-/// Initialize interrupt handlers.
+const PIT_IRQ: u8 = 0;
+const KEYBOARD_IRQ: u8 = 1;
+const SCANCODE_BUFFER_SIZE: usize = 64;
+
+/// Install the exception handlers on the bootstrap processor before the memory manager and the
+/// heap are initialized, so faults during their initialization are reported instead of resetting
+/// the processor. The handlers run on the current stack until [`init`] allocates the interrupt
+/// stacks.
 ///
 /// # Panics
 /// This function will panic if it is called more than once.
+pub fn init_early() {
+    EARLY_IDT.call_once(|| new_idt(false)).load();
+}
+
+/// Initialize interrupt handlers on the bootstrap processor.
+///
+/// # Panics
+/// This function will panic if it is called more than once or before the memory manager and the
+/// heap are initialized.
 pub fn init() {
-    init_gdt();
+    init_gdt(0);
     init_idt();
+    machine_check::init_cpu();
     init_pic();
+    apic::init();
+}
+
+/// Initialize interrupt handling on the application processor `cpu`, which shares the IDT with
+/// the bootstrap processor but has its own GDT, TSS and interrupt stacks.
+///
+/// # Panics
+/// This function will panic if it is called before [`init`].
+pub fn init_application_processor(cpu: usize) {
+    init_gdt(cpu);
+    IDT.get().expect("IDT is not initialized").load();
+    machine_check::init_cpu();
+    apic::init_cpu();
 }
 
 /// Enable interrupts if they are not enabled.
@@ -57,8 +105,13 @@ pub fn enable_interrupts() {
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
 pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
+    /// Timer of the local APIC of every processor
+    LocalTimer = 0xef,
+    /// Inter-processor interrupt running the calls queued by [`crate::smp::call_on_cpu`]
+    CallFunction = 0xfc,
+    /// Inter-processor interrupt flushing the TLB, see [`crate::memory::tlb`]
+    TlbShootdown = 0xfd,
+    Spurious = 0xff,
 }
 
 impl From<InterruptIndex> for u8 {
@@ -73,12 +126,6 @@ impl From<InterruptIndex> for usize {
     }
 }
 
-#[derive(Debug)]
-struct SegmentSelectors {
-    code_selector: gdt::SegmentSelector,
-    tss_selector: gdt::SegmentSelector,
-}
-
 /// Initialize the Interrupt Descriptor Table (IDT).
 ///
 /// IDT stores function pointers to interrupt handlers.
@@ -91,91 +138,139 @@ struct SegmentSelectors {
 /// # Panics
 /// This function will panic if it is called more than once.
 fn init_idt() {
-    let idt = IDT.call_once(|| {
-        let mut idt = InterruptDescriptorTable::new();
-
-        // # exceptions
-        idt.alignment_check.set_handler_fn(alignment_check_handler);
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.bound_range_exceeded
-            .set_handler_fn(bound_range_exceeded_handler);
-        idt.debug.set_handler_fn(debug_handler);
-        idt.invalid_tss.set_handler_fn(invalid_tss_handler);
-        idt.stack_segment_fault
-            .set_handler_fn(stack_segment_fault_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt.virtualization.set_handler_fn(virtualization_handler);
+    IDT.call_once(|| new_idt(true)).load();
+}
+
+/// Create an IDT with all the handlers of the kernel, running the double fault and NMI handlers
+/// on their interrupt stacks if `interrupt_stacks` is set.
+fn new_idt(interrupt_stacks: bool) -> InterruptDescriptorTable {
+    let mut idt = InterruptDescriptorTable::new();
+
+    // # exceptions
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    // # Safety
+    // the entries are trampolines generated by `exception_entry!` for exceptions with an
+    // error code, which is what they expect
+    unsafe {
+        idt.alignment_check
+            .set_handler_addr(VirtAddr::new(alignment_check_entry as usize as u64));
         idt.vmm_communication_exception
-            .set_handler_fn(vmm_communication_exception_handler);
-        idt.general_protection_fault
-            .set_handler_fn(general_protection_fault_handler);
+            .set_handler_addr(VirtAddr::new(
+                vmm_communication_exception_entry as usize as u64,
+            ));
+        idt.segment_not_present
+            .set_handler_addr(VirtAddr::new(segment_not_present_entry as usize as u64));
+        idt.security_exception
+            .set_handler_addr(VirtAddr::new(security_exception_entry as usize as u64));
+        idt.invalid_tss
+            .set_handler_addr(VirtAddr::new(invalid_tss_entry as usize as u64));
+        idt.stack_segment_fault
+            .set_handler_addr(VirtAddr::new(stack_segment_fault_entry as usize as u64));
+        idt.page_fault
+            .set_handler_addr(VirtAddr::new(page_fault_entry as usize as u64));
+        idt.general_protection_fault.set_handler_addr(VirtAddr::new(
+            general_protection_fault_entry as usize as u64,
+        ));
+    }
+    // # Safety
+    // the entry is a paranoid trampoline generated by `exception_entry!` for an exception with
+    // an error code
+    let double_fault = unsafe {
+        idt.double_fault
+            .set_handler_addr(VirtAddr::new(double_fault_entry as usize as u64))
+    };
+    if interrupt_stacks {
+        // # Safety
+        // `DOUBLE_FAULT_IST_INDEX` has a corresponding entry in IST and is not used by any
+        // other interrupt handler
+        unsafe {
+            double_fault.set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        }
+    }
+    let nmi = idt.non_maskable_interrupt.set_handler_fn(nmi::nmi_handler);
+    if interrupt_stacks {
         // # Safety
-        // `DOUBLE_FAULT_IST_INDEX` has a corresponding entry in IST and is not used by any other
+        // `NMI_IST_INDEX` has a corresponding entry in IST and is not used by any other
         // interrupt handler
         unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+            nmi.set_stack_index(NMI_IST_INDEX);
         }
-
-        idt[InterruptIndex::Timer.into()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.into()].set_handler_fn(keyboard_interrupt_handler);
-
-        idt
-    });
-    idt.load();
+    }
+    idt.machine_check
+        .set_handler_fn(machine_check::machine_check_handler);
+
+    // device interrupts, the local APIC vectors above them have their own handlers
+    irq::init_idt(&mut idt);
+
+    // the exceptions and vectors without a handler of their own are reported
+    idt.divide_error.set_handler_fn(unexpected_exception::<0>);
+    idt.overflow.set_handler_fn(unexpected_exception::<4>);
+    idt.invalid_opcode.set_handler_fn(unexpected_exception::<6>);
+    idt.device_not_available
+        .set_handler_fn(unexpected_exception::<7>);
+    idt.x87_floating_point
+        .set_handler_fn(unexpected_exception::<16>);
+    idt.simd_floating_point
+        .set_handler_fn(unexpected_exception::<19>);
+    macro_rules! unexpected_interrupts {
+            ($($vector:literal),*) => {
+                $(idt[$vector].set_handler_fn(unexpected_interrupt::<$vector>);)*
+            };
+        }
+    unexpected_interrupts!(
+        0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfe
+    );
+    idt[InterruptIndex::LocalTimer.into()].set_handler_fn(local_timer_interrupt_handler);
+    idt[InterruptIndex::CallFunction.into()].set_handler_fn(call_function_interrupt_handler);
+    idt[InterruptIndex::TlbShootdown.into()].set_handler_fn(tlb_shootdown_interrupt_handler);
+    idt[InterruptIndex::Spurious.into()].set_handler_fn(spurious_interrupt_handler);
+
+    idt
 }
 
-/// Initialize the Global Descriptor Table (GDT.
+/// Initialize the Global Descriptor Table (GDT) of the processor `cpu`.
 ///
 /// GDT contains the _segments_ of the program. Each segment describes a different purpose.
 /// The TSS segment's interrupt stack table holds pointers to stacks dedicated for interrupt
-/// handlers.
+/// handlers. The stacks are allocated with guard pages, see [`stack`]. Every processor needs its
+/// own TSS, as loading a TSS marks its descriptor busy, and its own interrupt stacks.
 ///
 /// Refer to [`GlobalDescriptorTable`] for more information.
 ///
 /// # Panics
-/// This function will panic if it is called more than once.
-fn init_gdt() {
-    let tss = TSS.call_once(|| {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[usize::from(DOUBLE_FAULT_IST_INDEX)] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            // FIXME: use dynamic memory allocation instead of statically allocated stack
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            // # Safety
-            // This code can be called only once so there will be no data races
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            // Return end address as stacks are filled from the end to the beginning
-            stack_start + STACK_SIZE
-        };
-        tss
-    });
-
-    let gdt = GDT.call_once(|| {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(gdt::Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(tss));
-        SEGMENT_SELECTORS.call_once(|| SegmentSelectors {
-            code_selector,
-            tss_selector,
-        });
-        gdt
-    });
+/// This function will panic if the interrupt stacks cannot be allocated.
+fn init_gdt(cpu: usize) {
+    let mut tss = TaskStateSegment::new();
+    let name = format!("double fault handler (CPU {cpu})").leak();
+    let stack = stack::allocate(name, DOUBLE_FAULT_STACK_SIZE)
+        .expect("failed to allocate the double fault stack");
+    tss.interrupt_stack_table[usize::from(DOUBLE_FAULT_IST_INDEX)] = stack.top();
+    let name = format!("NMI handler (CPU {cpu})").leak();
+    let stack = stack::allocate(name, NMI_STACK_SIZE).expect("failed to allocate the NMI stack");
+    tss.interrupt_stack_table[usize::from(NMI_IST_INDEX)] = stack.top();
+    // the tables are used as long as the processor runs
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.add_entry(gdt::Descriptor::kernel_code_segment());
+    let tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(tss));
+    let gdt: &'static GlobalDescriptorTable = Box::leak(Box::new(gdt));
 
     gdt.load();
 
-    let selectors = SEGMENT_SELECTORS.get().unwrap();
-
     // # Safety
     // Above we ensure that the code and TSS selectors point to valid entries
     unsafe {
-        // At this point the SS (stack segment) register contains selector with index 2,
-        // which happens to be the index of TSS. Set it to 0 to avoid issues.
-        // You can call `println!("{:?}", (SS::get_reg(), selectors));` to see this for yourself.
+        // At this point the SS (stack segment) register of the bootstrap processor contains
+        // selector with index 2, which happens to be the index of TSS. Set it to 0 to avoid
+        // issues.
         SS::set_reg(gdt::SegmentSelector::NULL);
-        CS::set_reg(selectors.code_selector);
-        tables::load_tss(selectors.tss_selector);
+        CS::set_reg(code_selector);
+        tables::load_tss(tss_selector);
     }
 }
 
@@ -192,17 +287,21 @@ fn init_pic() {
         // we ensure that the PICs does not overlap and the offsets are correct
         let chained_pics = unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) };
 
-        Mutex::new(chained_pics)
+        IrqSpinLock::new(chained_pics)
     });
 
-    // initialize keyboard before enabling interrupts
-    init_keyboard();
-
     // # Safety
     // we ensure that the PICs are properly configured
     unsafe {
         pics.lock().initialize();
     }
+    irq::init_pic();
+
+    // the timer interrupts are only acknowledged
+    irq::register_irq(PIT_IRQ, || IrqReturn::Handled).expect("failed to register the timer");
+    init_keyboard();
+    irq::register_irq(KEYBOARD_IRQ, keyboard_interrupt_handler)
+        .expect("failed to register the keyboard");
 }
 
 fn init_keyboard() {
@@ -213,121 +312,310 @@ fn init_keyboard() {
             HandleControl::Ignore,
         );
 
-        Mutex::new(keyboard)
+        IrqSpinLock::new(keyboard)
     });
 }
 
 // Exception handlers
 extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&frame);
+    stats::record(3);
     // FIXME handle breakpoint
     println!("Exception: breakpoint\n{:#?}", frame)
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    let accessed_addr = Cr2::read();
+exception_entry!(page_fault_entry => page_fault_handler, error_code);
+exception_entry!(invalid_tss_entry => invalid_tss_handler, error_code);
+exception_entry!(stack_segment_fault_entry => stack_segment_fault_handler, error_code);
+exception_entry!(general_protection_fault_entry => general_protection_fault_handler, error_code);
+exception_entry!(alignment_check_entry => alignment_check_handler, error_code);
+exception_entry!(
+    vmm_communication_exception_entry => vmm_communication_exception_handler,
+    error_code
+);
+exception_entry!(double_fault_entry => double_fault_handler, error_code, paranoid);
+exception_entry!(
+    segment_not_present_entry => unexpected_exception_with_error_code::<11>,
+    error_code
+);
+exception_entry!(
+    security_exception_entry => unexpected_exception_with_error_code::<30>,
+    error_code
+);
+
+extern "C" fn page_fault_handler(context: &mut ExceptionContext) {
+    stats::record(14);
+    let dump = RegisterDump::capture(context);
+    let error_code = PageFaultErrorCode::from_bits_truncate(context.error_code);
+    let accessed_addr = VirtAddr::new_truncate(dump.system.cr2);
+
+    if let Some(stack) = stack::overflowed_stack(accessed_addr) {
+        panic!(
+            "Exception: stack overflow in thread {}\n{dump}",
+            stack.name()
+        );
+    }
 
+    // a fault of code holding the memory manager cannot be resolved
+    let result = MEMORY_MANAGER
+        .get()
+        .and_then(|manager| manager.lock_in_exception())
+        .map(|mut manager| manager.handle_page_fault(accessed_addr, error_code));
+    let reason = match result {
+        Some(Ok(())) => return,
+        Some(Err(PageFaultError::SegmentationViolation)) => "segmentation violation",
+        Some(Err(PageFaultError::AccessViolation)) => "access violation",
+        Some(Err(PageFaultError::OutOfMemory)) => "out of memory",
+        None => "memory manager unavailable",
+    };
+
+    // faults of the user copy routines are reported to their callers
+    if let Some(fixup) = user::exception_fixup(context.rip) {
+        context.rip = fixup;
+        return;
+    }
+
+    // there are no tasks yet, so every unresolved fault is fatal to the kernel
     panic!(
-        "Exception: page fault\n\
-         Accessed address: {accessed_addr:?}\n\
-         Error code: {error_code:?}\n\
-         {frame:#?}",
+        "Exception: page fault ({reason})\n\
+         Accessed address: {:#x}\n\
+         Cause: {} (error code {:#x})\n\
+         {dump}",
+        dump.system.cr2,
+        PageFaultDescription(error_code),
+        context.error_code,
     )
 }
 
-extern "x86-interrupt" fn invalid_tss_handler(frame: InterruptStackFrame, _error_code: u64) {
-    panic!("Exception: invalid tss\n{:#?}", frame)
+extern "C" fn invalid_tss_handler(context: &mut ExceptionContext) {
+    stats::record(10);
+    let dump = RegisterDump::capture(context);
+    panic!(
+        "Exception: invalid tss\n{}\n{dump}",
+        SelectorError(context.error_code)
+    )
 }
 
-extern "x86-interrupt" fn alignment_check_handler(frame: InterruptStackFrame, _error_code: u64) {
-    panic!("Exception: alignment check\n{:#?}", frame)
+extern "C" fn alignment_check_handler(context: &mut ExceptionContext) {
+    stats::record(17);
+    let dump = RegisterDump::capture(context);
+    panic!("Exception: alignment check\n{dump}")
 }
 
 extern "x86-interrupt" fn bound_range_exceeded_handler(frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&frame);
+    stats::record(5);
     panic!("Exception: bounds range exceeded\n{:#?}", frame)
 }
 
 extern "x86-interrupt" fn debug_handler(frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&frame);
+    stats::record(1);
     panic!("Exception: debug\n{:#?}", frame)
 }
 
-extern "x86-interrupt" fn stack_segment_fault_handler(
-    frame: InterruptStackFrame,
-    _error_code: u64,
-) {
-    panic!("Exception: stack segment fault\n{:#?}", frame)
+extern "C" fn stack_segment_fault_handler(context: &mut ExceptionContext) {
+    stats::record(12);
+    let dump = RegisterDump::capture(context);
+    panic!(
+        "Exception: stack segment fault\n{}\n{dump}",
+        SelectorError(context.error_code)
+    )
 }
 
 extern "x86-interrupt" fn virtualization_handler(frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&frame);
+    stats::record(20);
     panic!("Exception: virtualization\n{:#?}", frame)
 }
 
-extern "x86-interrupt" fn vmm_communication_exception_handler(
-    frame: InterruptStackFrame,
-    _error_code: u64,
-) {
-    panic!("Exception: vmm communication exception\n{:#?}", frame)
+extern "C" fn vmm_communication_exception_handler(context: &mut ExceptionContext) {
+    stats::record(29);
+    let dump = RegisterDump::capture(context);
+    panic!(
+        "Exception: vmm communication exception (error code {:#x})\n{dump}",
+        context.error_code
+    )
 }
 
-extern "x86-interrupt" fn general_protection_fault_handler(
-    frame: InterruptStackFrame,
-    error_code: u64,
-) {
+extern "C" fn general_protection_fault_handler(context: &mut ExceptionContext) {
+    stats::record(13);
+    let dump = RegisterDump::capture(context);
     panic!(
-        "Exception: general protection fault\nerror code: {error_code}\n{:#?}",
+        "Exception: general protection fault\n{}\n{dump}",
+        SelectorError(context.error_code)
+    )
+}
+
+extern "C" fn double_fault_handler(context: &mut ExceptionContext) {
+    let _gs = percpu::KernelGsGuard::enter_paranoid();
+    stats::record(8);
+    let dump = RegisterDump::capture(context);
+    // overflowing a stack faults on its guard page, and the page fault cannot be delivered on
+    // the same stack
+    let accessed_addr = VirtAddr::new_truncate(dump.system.cr2);
+    if let Some(stack) = stack::overflowed_stack(accessed_addr)
+        .or_else(|| stack::overflowed_stack(VirtAddr::new_truncate(context.rsp)))
+    {
+        panic!(
+            "Exception: stack overflow in thread {}\n{dump}",
+            stack.name()
+        );
+    }
+    panic!("Exception: double fault\n{dump}");
+}
+
+extern "x86-interrupt" fn unexpected_exception<const VECTOR: u8>(frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&frame);
+    stats::record(VECTOR);
+    panic!(
+        "Exception: {} (vector {VECTOR})\n{:#?}",
+        stats::vector_name(VECTOR),
         frame
     )
 }
 
-extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, _error_code: u64) -> ! {
-    // FIXME handle double fault
-    panic!("Exception: double fault\n{:#?}", frame);
+extern "C" fn unexpected_exception_with_error_code<const VECTOR: u8>(
+    context: &mut ExceptionContext,
+) {
+    stats::record(VECTOR);
+    let dump = RegisterDump::capture(context);
+    panic!(
+        "Exception: {} (vector {VECTOR}, error code {:#x})\n{dump}",
+        stats::vector_name(VECTOR),
+        context.error_code
+    )
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // # Safety
-    // we use the same interrupt number as the handler is registered for
-    unsafe {
-        PICS.get()
-            .unwrap()
-            .lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.into());
+/// Logs an interrupt on a vector nothing should raise.
+extern "x86-interrupt" fn unexpected_interrupt<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    let _interrupt = percpu::enter_interrupt();
+    stats::record(VECTOR);
+    println!(
+        "unexpected interrupt on vector {VECTOR:#x} on CPU {}",
+        percpu::cpu_id()
+    );
+    // software interrupts are not acknowledged
+    if apic::is_in_service(VECTOR) {
+        apic::end_of_interrupt();
     }
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn local_timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    let interrupt = percpu::enter_interrupt();
+    stats::record(InterruptIndex::LocalTimer.into());
+    if percpu::cpu_id() == 0 {
+        work::tick();
+    }
+    apic::end_of_interrupt();
+    drop(interrupt);
+    work::run_pending();
+}
+
+extern "x86-interrupt" fn call_function_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    let _interrupt = percpu::enter_interrupt();
+    stats::record(InterruptIndex::CallFunction.into());
+    smp::run_pending_calls();
+    apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn tlb_shootdown_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    let _interrupt = percpu::enter_interrupt();
+    stats::record(InterruptIndex::TlbShootdown.into());
+    tlb::flush_pending();
+    apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    let _interrupt = percpu::enter_interrupt();
+    stats::record(InterruptIndex::Spurious.into());
+    // spurious interrupts must not be acknowledged
+}
+
+fn keyboard_interrupt_handler() -> IrqReturn {
     // # Safety
     // we read from the keyboard port only on keyboard interrupt
     let scancode: u8 = unsafe { Port::new(PS2_CONTROLLER_PORT).read() };
+    SCANCODES.lock().push(scancode);
+    KEYBOARD_WORK.schedule();
+    IrqReturn::Handled
+}
+
+/// Decodes and prints the scancodes read by the keyboard interrupt handler.
+fn process_scancodes() {
+    loop {
+        let Some(scancode) = SCANCODES.lock().pop() else {
+            break;
+        };
+        process_scancode(scancode);
+    }
+}
 
+fn process_scancode(scancode: u8) {
     let mut keyboard = KEYBOARD.get().unwrap().lock();
 
     match keyboard.add_byte(scancode) {
         Ok(Some(key_event)) => {
+            if let KeyCode::LShift | KeyCode::RShift = key_event.code {
+                SHIFT_PRESSED.store(key_event.state == KeyState::Down, Ordering::Relaxed);
+            }
+
             if let Some(key) = keyboard.process_keyevent(key_event) {
-                use pc_keyboard::DecodedKey::Unicode;
+                use pc_keyboard::DecodedKey::{RawKey, Unicode};
+                let shift_pressed = SHIFT_PRESSED.load(Ordering::Relaxed);
                 match key {
+                    RawKey(KeyCode::PageUp) if shift_pressed => logger::page_up(),
+                    RawKey(KeyCode::PageDown) if shift_pressed => logger::page_down(),
                     Unicode('\x1b') => print!("ESC"),
                     Unicode('\x08') => print!("BS"),
                     Unicode('\x7f') => print!("DEL"),
                     Unicode('\t') => print!("TAB"), // FIXME: tab not supported in logger?
                     Unicode(character) => print!("{}", character),
-                    pc_keyboard::DecodedKey::RawKey(key) => print!("RAW[{:?}]", key),
+                    RawKey(key) => print!("RAW[{:?}]", key),
                 }
             }
         }
         Ok(None) => {}
         Err(e) => println!("Keyboard error: {:?}", e),
     }
+}
 
-    // # Safety
-    // we use the same interrupt number as the handler is registered for
-    unsafe {
-        PICS.get()
-            .unwrap()
-            .lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.into());
+/// Scancodes which have not been processed yet, the oldest are dropped when it is full.
+struct Scancodes {
+    buffer: [u8; SCANCODE_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl Scancodes {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; SCANCODE_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, scancode: u8) {
+        if self.len == self.buffer.len() {
+            self.pop();
+        }
+        self.buffer[(self.start + self.len) % self.buffer.len()] = scancode;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let scancode = self.buffer[self.start];
+        self.start = (self.start + 1) % self.buffer.len();
+        self.len -= 1;
+        Some(scancode)
     }
 }
@@ -0,0 +1,195 @@
+//! Local APIC of the processors.
+//!
+//! Every processor has a local APIC at the same physical address, which delivers its timer
+//! interrupts and inter-processor interrupts. The legacy PICs stay in use for the devices, they
+//! are connected to the local APIC of the bootstrap processor.
+
+use super::InterruptIndex;
+use crate::memory::MEMORY_MANAGER;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Once;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+
+const ID: usize = 0x20;
+const END_OF_INTERRUPT: usize = 0xb0;
+const IN_SERVICE: usize = 0x100;
+const SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE_CONFIGURATION: usize = 0x3e0;
+
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const INIT: u32 = 0b101 << 8;
+const STARTUP: u32 = 0b110 << 8;
+const NMI: u32 = 0b100 << 8;
+const LEVEL_ASSERT: u32 = 1 << 14;
+
+/// Frequency of the timer interrupts of every processor.
+pub const TIMER_FREQUENCY: u32 = 100;
+
+static LOCAL_APIC: Once<VirtAddr> = Once::new();
+static TIMER_TICKS_PER_MILLISECOND: AtomicU32 = AtomicU32::new(0);
+
+/// Maps the local APIC, calibrates its timer and enables it on the bootstrap processor.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized.
+pub(super) fn init() {
+    LOCAL_APIC.call_once(|| {
+        // # Safety
+        // the MSR exists on every CPU with a local APIC
+        let base = unsafe { Msr::new(IA32_APIC_BASE).read() } & !0xfff;
+        MEMORY_MANAGER
+            .get()
+            .unwrap()
+            .lock()
+            .map_device_memory(PhysAddr::new(base), 4096)
+            .expect("failed to map the local APIC")
+    });
+
+    // count the timer ticks in 10 ms
+    write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+    write(LVT_TIMER, LVT_MASKED);
+    write(TIMER_INITIAL_COUNT, u32::MAX);
+    wait_microseconds(10_000);
+    let ticks = u32::MAX - read(TIMER_CURRENT_COUNT);
+    write(TIMER_INITIAL_COUNT, 0);
+    TIMER_TICKS_PER_MILLISECOND.store(ticks / 10, Ordering::Relaxed);
+
+    init_cpu();
+}
+
+/// Enables the local APIC of the current processor and starts its timer.
+///
+/// # Panics
+/// The function will panic if the local APIC has not been mapped by [`init`].
+pub(super) fn init_cpu() {
+    write(
+        SPURIOUS_INTERRUPT_VECTOR,
+        APIC_SOFTWARE_ENABLE | u32::from(u8::from(InterruptIndex::Spurious)),
+    );
+
+    let ticks_per_millisecond = TIMER_TICKS_PER_MILLISECOND.load(Ordering::Relaxed);
+    write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+    write(
+        LVT_TIMER,
+        TIMER_PERIODIC | u32::from(u8::from(InterruptIndex::LocalTimer)),
+    );
+    write(
+        TIMER_INITIAL_COUNT,
+        (ticks_per_millisecond * 1000 / TIMER_FREQUENCY).max(1),
+    );
+}
+
+/// Returns the local APIC ID of the current processor.
+pub fn id() -> u32 {
+    read(ID) >> 24
+}
+
+/// Signals the end of the interrupt being handled to the local APIC.
+pub fn end_of_interrupt() {
+    write(END_OF_INTERRUPT, 0);
+}
+
+/// Returns whether the local APIC delivered `vector` and waits for its end of interrupt.
+pub fn is_in_service(vector: u8) -> bool {
+    let register = IN_SERVICE + usize::from(vector / 32) * 0x10;
+    read(register) & 1 << (vector % 32) != 0
+}
+
+/// Sends an INIT inter-processor interrupt, which resets the processor with `apic_id` into a
+/// state waiting for a startup interrupt.
+pub fn send_init(apic_id: u32) {
+    send(apic_id, INIT | LEVEL_ASSERT);
+}
+
+/// Sends a startup inter-processor interrupt, which starts the processor with `apic_id` in real
+/// mode at the start of the page `vector`.
+pub fn send_startup(apic_id: u32, vector: u8) {
+    send(apic_id, STARTUP | LEVEL_ASSERT | u32::from(vector));
+}
+
+/// Sends a non-maskable interrupt to the processor with `apic_id`.
+pub fn send_nmi(apic_id: u32) {
+    send(apic_id, NMI | LEVEL_ASSERT);
+}
+
+/// Sends an inter-processor interrupt with `vector` to the processor with `apic_id`.
+pub fn send_ipi(apic_id: u32, vector: u8) {
+    send(apic_id, LEVEL_ASSERT | u32::from(vector));
+}
+
+fn send(apic_id: u32, command: u32) {
+    // an interrupt sending an inter-processor interrupt itself would change the destination
+    interrupts::without_interrupts(|| {
+        write(INTERRUPT_COMMAND_HIGH, apic_id << 24);
+        write(INTERRUPT_COMMAND_LOW, command);
+        while read(INTERRUPT_COMMAND_LOW) & DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+/// Busy waits for `microseconds` using channel 2 of the programmable interval timer.
+///
+/// The channel is not shared, only one processor may wait at a time.
+pub fn wait_microseconds(microseconds: u64) {
+    const PIT_FREQUENCY: u64 = 1_193_182;
+    const GATE: u8 = 1 << 0;
+    const SPEAKER: u8 = 1 << 1;
+    const OUTPUT: u8 = 1 << 5;
+    // channel 2, low and high byte, interrupt on terminal count
+    const ONE_SHOT: u8 = 0b1011_0000;
+
+    let mut control = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut data = Port::<u8>::new(0x42);
+
+    let mut remaining = (microseconds * PIT_FREQUENCY).div_ceil(1_000_000);
+    while remaining > 0 {
+        let count = remaining.min(u64::from(u16::MAX)) as u16;
+        remaining -= u64::from(count);
+        // # Safety
+        // channel 2 is only used for waiting, the speaker stays off
+        unsafe {
+            let value = control.read() & !(GATE | SPEAKER);
+            control.write(value);
+            command.write(ONE_SHOT);
+            data.write(count as u8);
+            data.write((count >> 8) as u8);
+            control.write(value | GATE);
+            while control.read() & OUTPUT == 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+fn register(offset: usize) -> *mut u32 {
+    let base = LOCAL_APIC.get().expect("local APIC is not mapped");
+    (*base + offset as u64).as_mut_ptr()
+}
+
+fn read(offset: usize) -> u32 {
+    // # Safety
+    // the register is part of the mapped local APIC page
+    unsafe { register(offset).read_volatile() }
+}
+
+fn write(offset: usize, value: u32) {
+    // # Safety
+    // the register is part of the mapped local APIC page
+    unsafe { register(offset).write_volatile(value) }
+}
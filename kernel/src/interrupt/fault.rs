@@ -0,0 +1,345 @@
+//! Register dumps and error code decoding for CPU exceptions.
+//!
+//! Exceptions which need the complete register state are entered through a naked trampoline
+//! generated by [`exception_entry!`]. It saves all general purpose registers next to the
+//! interrupt stack frame, forming an [`ExceptionContext`], and calls the handler with a mutable
+//! reference to it. The registers are restored from the context when the handler returns, so
+//! handlers may resume the interrupted code with modified state.
+
+use crate::memory::MEMORY_MANAGER;
+use core::fmt;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+use x86_64::registers::segmentation::{Segment as _, DS, ES, FS, GS};
+use x86_64::structures::idt::{DescriptorTable, PageFaultErrorCode, SelectorErrorCode};
+use x86_64::VirtAddr;
+
+/// Number of instruction bytes shown at the faulting instruction pointer.
+const INSTRUCTION_BYTES: usize = 16;
+
+/// Generates the entry point of an exception handler taking `&mut ExceptionContext`.
+///
+/// Exceptions without an error code push a zero in its place, so all handlers see the same
+/// layout. Exceptions from user mode swap the GS base on entry and exit. Paranoid entries leave
+/// the GS base alone, their handlers use [`crate::percpu::KernelGsGuard::enter_paranoid`]. The
+/// trampoline also builds a stack frame pointing at the interrupted instruction, so backtraces
+/// continue into the interrupted code.
+macro_rules! exception_entry {
+    ($name:ident => $handler:path) => {
+        exception_entry!(@entry $name, $handler, "push 0", "swapgs");
+    };
+    ($name:ident => $handler:path, error_code) => {
+        exception_entry!(@entry $name, $handler, "", "swapgs");
+    };
+    ($name:ident => $handler:path, error_code, paranoid) => {
+        exception_entry!(@entry $name, $handler, "", "");
+    };
+    (@entry $name:ident, $handler:path, $push_error_code:literal, $swapgs:literal) => {
+        #[naked]
+        unsafe extern "C" fn $name() -> ! {
+            core::arch::asm!(
+                $push_error_code,
+                // the kernel's GS base is swapped in when entering from user mode, see `percpu`
+                "test qword ptr [rsp + 16], 3",
+                "jz 2f",
+                $swapgs,
+                "2:",
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                // frame record of the interrupted code: saved rbp and the instruction pointer
+                "push qword ptr [rsp + 16 * 8]",
+                "push rbp",
+                "mov rbp, rsp",
+                // the CPU aligns the stack to 16 bytes before pushing the interrupt frame
+                "sub rsp, 8",
+                "cld",
+                "call {handler}",
+                "lea rsp, [rbp + 16]",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "add rsp, 8",
+                "test qword ptr [rsp + 8], 3",
+                "jz 3f",
+                $swapgs,
+                "3:",
+                "iretq",
+                handler = sym $handler,
+                options(noreturn),
+            )
+        }
+    };
+}
+
+pub(super) use exception_entry;
+
+/// Registers saved by the exception trampoline, followed by the interrupt stack frame.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExceptionContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// Error code pushed by the CPU, zero for exceptions without one
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Control, model specific and segment registers at the time of an exception.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemRegisters {
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+    pub ds: u16,
+    pub es: u16,
+    pub fs: u16,
+    pub gs: u16,
+}
+
+impl SystemRegisters {
+    /// Reads the registers of the current CPU.
+    ///
+    /// Exception handlers must call this before anything else can fault and overwrite CR2.
+    pub fn read() -> Self {
+        let (frame, flags) = Cr3::read_raw();
+        Self {
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read_raw(),
+            cr3: frame.start_address().as_u64() | u64::from(flags),
+            cr4: Cr4::read_raw(),
+            efer: Efer::read_raw(),
+            ds: DS::get_reg().0,
+            es: ES::get_reg().0,
+            fs: FS::get_reg().0,
+            gs: GS::get_reg().0,
+        }
+    }
+}
+
+/// Complete machine state of an exception, printed as a register dump.
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterDump {
+    pub context: ExceptionContext,
+    pub system: SystemRegisters,
+}
+
+impl RegisterDump {
+    pub fn capture(context: &ExceptionContext) -> Self {
+        Self {
+            system: SystemRegisters::read(),
+            context: *context,
+        }
+    }
+}
+
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ExceptionContext {
+            r15,
+            r14,
+            r13,
+            r12,
+            r11,
+            r10,
+            r9,
+            r8,
+            rbp,
+            rdi,
+            rsi,
+            rdx,
+            rcx,
+            rbx,
+            rax,
+            rip,
+            cs,
+            rflags,
+            rsp,
+            ss,
+            ..
+        } = self.context;
+        let SystemRegisters {
+            cr0,
+            cr2,
+            cr3,
+            cr4,
+            efer,
+            ds,
+            es,
+            fs,
+            gs,
+        } = self.system;
+
+        writeln!(f, "RIP: {cs:04x}:{rip:016x} RSP: {ss:04x}:{rsp:016x}")?;
+        writeln!(f, "RFLAGS: {rflags:016x}")?;
+        writeln!(f, "RAX: {rax:016x} RBX: {rbx:016x} RCX: {rcx:016x}")?;
+        writeln!(f, "RDX: {rdx:016x} RSI: {rsi:016x} RDI: {rdi:016x}")?;
+        writeln!(f, "RBP: {rbp:016x} R8:  {r8:016x} R9:  {r9:016x}")?;
+        writeln!(f, "R10: {r10:016x} R11: {r11:016x} R12: {r12:016x}")?;
+        writeln!(f, "R13: {r13:016x} R14: {r14:016x} R15: {r15:016x}")?;
+        writeln!(f, "CR0: {cr0:016x} CR2: {cr2:016x} CR3: {cr3:016x}")?;
+        writeln!(f, "CR4: {cr4:016x} EFER: {efer:016x}")?;
+        writeln!(
+            f,
+            "CS: {cs:04x} SS: {ss:04x} DS: {ds:04x} ES: {es:04x} FS: {fs:04x} GS: {gs:04x}"
+        )?;
+        write!(f, "Code:")?;
+        match instruction_bytes(rip) {
+            Some((bytes, len)) if len > 0 => {
+                for byte in &bytes[..len] {
+                    write!(f, " {byte:02x}")?;
+                }
+                Ok(())
+            }
+            _ => write!(f, " <unavailable>"),
+        }
+    }
+}
+
+/// Reads the bytes at `rip` which are mapped, stopping at the first unmapped page.
+///
+/// Returns [`None`] if the page tables cannot be inspected, e.g. when the exception interrupted
+/// the memory manager.
+fn instruction_bytes(rip: u64) -> Option<([u8; INSTRUCTION_BYTES], usize)> {
+    let manager = MEMORY_MANAGER.get()?.try_lock()?;
+    let mut bytes = [0; INSTRUCTION_BYTES];
+    let mut len = 0;
+    for (offset, byte) in bytes.iter_mut().enumerate() {
+        let Ok(address) = VirtAddr::try_new(rip.wrapping_add(offset as u64)) else {
+            break;
+        };
+        // checking the first byte of every page is enough
+        if (offset == 0 || address.is_aligned(4096u64))
+            && manager.translate_address(address).is_none()
+        {
+            break;
+        }
+        // # Safety
+        // the page containing the address is mapped
+        *byte = unsafe { address.as_ptr::<u8>().read_volatile() };
+        len += 1;
+    }
+    Some((bytes, len))
+}
+
+/// Plain English description of a selector error code, as pushed by invalid TSS, segment not
+/// present, stack segment and general protection faults.
+#[derive(Debug, Clone, Copy)]
+pub struct SelectorError(pub u64);
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(code) = SelectorErrorCode::new(self.0) else {
+            return write!(f, "invalid selector error code {:#x}", self.0);
+        };
+        if self.0 == 0 {
+            return write!(f, "error code 0 (not related to a segment selector)");
+        }
+        let table = match code.descriptor_table() {
+            DescriptorTable::Gdt => "GDT",
+            DescriptorTable::Idt => "IDT",
+            DescriptorTable::Ldt => "LDT",
+        };
+        write!(
+            f,
+            "selector {:#06x}: entry {} of the {table}",
+            self.0,
+            code.index()
+        )?;
+        if code.external() {
+            write!(f, ", caused by an external event")?;
+        }
+        Ok(())
+    }
+}
+
+/// Plain English description of a page fault error code.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFaultDescription(pub PageFaultErrorCode);
+
+impl fmt::Display for PageFaultDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0;
+        let mode = if code.contains(PageFaultErrorCode::USER_MODE) {
+            "user-mode"
+        } else {
+            "kernel-mode"
+        };
+        let access = if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            "instruction fetch from"
+        } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            "write to"
+        } else {
+            "read from"
+        };
+        if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            write!(f, "{mode} {access} a present page violated its protection")?;
+        } else {
+            write!(f, "{mode} {access} a non-present page")?;
+        }
+
+        let details = [
+            (
+                PageFaultErrorCode::MALFORMED_TABLE,
+                "reserved bit set in a page table entry",
+            ),
+            (
+                PageFaultErrorCode::PROTECTION_KEY,
+                "protection key violation",
+            ),
+            (PageFaultErrorCode::SHADOW_STACK, "shadow stack access"),
+            (PageFaultErrorCode::SGX, "SGX access control violation"),
+            (PageFaultErrorCode::RMP, "RMP violation"),
+        ];
+        for (flag, description) in details {
+            if code.contains(flag) {
+                write!(f, ", {description}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+//! Device interrupt handlers registered at runtime.
+//!
+//! Every vector from [`FIRST_VECTOR`] up to the local APIC vectors enters a generic stub, which
+//! runs the handlers registered for the vector and signals the end of the interrupt to the
+//! controller that raised it: the PICs for the legacy IRQ lines, the local APIC for the vectors
+//! handed out to message signaled interrupts (MSI). The work deferred by the handlers runs
+//! afterwards, see [`work`].
+//!
+//! Legacy IRQ lines may be shared by several devices, so all handlers of a line run on every
+//! interrupt and report whether their device raised it. A line is masked while it has no
+//! handlers.
+//!
+//! The lowest priority line of each PIC, IRQ 7 and IRQ 15, is also raised for spurious interrupts,
+//! e.g. when a device deasserts its line before the processor acknowledged it. The in-service
+//! register of the PIC tells them apart. A spurious interrupt is not acknowledged, except that
+//! the first PIC has to be told about the end of a spurious interrupt of the second one, which
+//! it saw as an interrupt of the cascade.
+
+use super::{apic, stats, work, InterruptIndex, PICS, PIC_1_OFFSET};
+use crate::sync::IrqSpinLock;
+use crate::{percpu, println};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// Number of legacy IRQ lines of the two PICs.
+pub const IRQ_LINES: u8 = 16;
+/// The first vector entering the generic stubs, IRQ line 0.
+pub const FIRST_VECTOR: u8 = PIC_1_OFFSET;
+/// The first vector handed out for MSI.
+const FIRST_MSI_VECTOR: u8 = FIRST_VECTOR + IRQ_LINES;
+/// The vectors above are used by the local APIC.
+const LAST_MSI_VECTOR: u8 = InterruptIndex::LocalTimer as u8 - 1;
+/// The second PIC is connected to this line of the first one.
+const CASCADE_LINE: u8 = 2;
+/// The lines raised for spurious interrupts of the first and the second PIC.
+const SPURIOUS_LINES: [u8; 2] = [7, 15];
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xa0;
+/// Selects the in-service register for the next read of the command port.
+const READ_IN_SERVICE: u8 = 0x0b;
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// Address of the local APIC of the destination processor in MSI addresses.
+const MSI_ADDRESS_BASE: u64 = 0xfee0_0000;
+
+// only used to initialize the array of handlers
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLERS: IrqSpinLock<Vec<Handler>> = IrqSpinLock::new(Vec::new());
+
+static HANDLERS: [IrqSpinLock<Vec<Handler>>; 256] = [NO_HANDLERS; 256];
+/// Vectors handed out for MSI
+static ALLOCATED: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static UNHANDLED: AtomicU64 = AtomicU64::new(0);
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a handler's device raised the interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    /// The interrupt came from another device sharing the line
+    NotMine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The IRQ line does not exist or is the cascade of the second PIC
+    InvalidLine(u8),
+    /// All MSI vectors are in use
+    NoFreeVector,
+}
+
+/// Identifies a registered handler for [`unregister`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerId {
+    vector: u8,
+    id: u64,
+}
+
+impl HandlerId {
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+}
+
+/// A vector allocated for a message signaled interrupt with its handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msi {
+    handler: HandlerId,
+    apic_id: u32,
+}
+
+impl Msi {
+    pub fn vector(&self) -> u8 {
+        self.handler.vector
+    }
+
+    /// Value for the message address register of the device, the interrupt is delivered to the
+    /// processor which allocated the vector.
+    pub fn address(&self) -> u64 {
+        MSI_ADDRESS_BASE | u64::from(self.apic_id) << 12
+    }
+
+    /// Value for the message data register of the device, an edge triggered interrupt with fixed
+    /// delivery.
+    pub fn data(&self) -> u16 {
+        u16::from(self.vector())
+    }
+}
+
+struct Handler {
+    id: u64,
+    handler: Box<dyn FnMut() -> IrqReturn + Send>,
+}
+
+/// Returns the number of interrupts which no handler claimed.
+pub fn unhandled() -> u64 {
+    UNHANDLED.load(Ordering::Relaxed)
+}
+
+/// Returns the number of spurious interrupts of the PICs.
+pub fn spurious() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+/// Adds `handler` to the handlers of the legacy IRQ `line` and unmasks the line.
+///
+/// The handler runs in interrupt context with interrupts disabled. It must not register or
+/// unregister handlers of its own vector.
+///
+/// # Panics
+/// The function will panic if the interrupts are not initialized.
+pub fn register_irq(
+    line: u8,
+    handler: impl FnMut() -> IrqReturn + Send + 'static,
+) -> Result<HandlerId, IrqError> {
+    if line >= IRQ_LINES || line == CASCADE_LINE {
+        return Err(IrqError::InvalidLine(line));
+    }
+    let id = add_handler(FIRST_VECTOR + line, Box::new(handler));
+    set_masked(line, false);
+    Ok(id)
+}
+
+/// Allocates a vector for a message signaled interrupt handled by `handler` on the current
+/// processor.
+pub fn allocate_msi(handler: impl FnMut() -> IrqReturn + Send + 'static) -> Result<Msi, IrqError> {
+    let vector = (FIRST_MSI_VECTOR..=LAST_MSI_VECTOR)
+        .find(|&vector| {
+            let (word, bit) = (usize::from(vector / 64), 1 << (vector % 64));
+            ALLOCATED[word].fetch_or(bit, Ordering::AcqRel) & bit == 0
+        })
+        .ok_or(IrqError::NoFreeVector)?;
+    Ok(Msi {
+        handler: add_handler(vector, Box::new(handler)),
+        apic_id: apic::id(),
+    })
+}
+
+/// Removes the handler and frees the vector of `msi`, the device must not use it anymore.
+pub fn free_msi(msi: Msi) {
+    unregister(msi.handler);
+    let vector = msi.vector();
+    ALLOCATED[usize::from(vector / 64)].fetch_and(!(1 << (vector % 64)), Ordering::AcqRel);
+}
+
+/// Removes a handler, a legacy IRQ line without handlers is masked again.
+///
+/// # Panics
+/// The function will panic if the interrupts are not initialized.
+pub fn unregister(id: HandlerId) {
+    let mut handlers = HANDLERS[usize::from(id.vector)].lock();
+    handlers.retain(|handler| handler.id != id.id);
+    if handlers.is_empty() {
+        if let Some(line) = legacy_line(id.vector) {
+            set_masked(line, true);
+        }
+    }
+}
+
+/// Points all vectors from [`FIRST_VECTOR`] up to the local APIC vectors at the generic stubs.
+pub(super) fn init_idt(idt: &mut InterruptDescriptorTable) {
+    for (vector, stub) in (FIRST_VECTOR..=LAST_MSI_VECTOR).zip(STUBS) {
+        idt[usize::from(vector)].set_handler_fn(stub);
+    }
+    idt[usize::from(FIRST_VECTOR + SPURIOUS_LINES[0])]
+        .set_handler_fn(spurious_line_stub::<{ SPURIOUS_LINES[0] }>);
+    idt[usize::from(FIRST_VECTOR + SPURIOUS_LINES[1])]
+        .set_handler_fn(spurious_line_stub::<{ SPURIOUS_LINES[1] }>);
+}
+
+/// Masks all legacy IRQ lines except for the cascade, they are unmasked when they get a handler.
+///
+/// # Panics
+/// The function will panic if the PICs are not initialized.
+pub(super) fn init_pic() {
+    // # Safety
+    // masking lines only stops their interrupts
+    unsafe {
+        PICS.get()
+            .unwrap()
+            .lock()
+            .write_masks(!(1 << CASCADE_LINE), 0xff);
+    }
+}
+
+fn add_handler(vector: u8, handler: Box<dyn FnMut() -> IrqReturn + Send>) -> HandlerId {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    HANDLERS[usize::from(vector)]
+        .lock()
+        .push(Handler { id, handler });
+    HandlerId { vector, id }
+}
+
+fn legacy_line(vector: u8) -> Option<u8> {
+    let line = vector.checked_sub(FIRST_VECTOR)?;
+    (line < IRQ_LINES).then_some(line)
+}
+
+fn set_masked(line: u8, masked: bool) {
+    let mut pics = PICS.get().expect("PICs are not initialized").lock();
+    // # Safety
+    // only the mask of `line` changes
+    unsafe {
+        let mut masks = pics.read_masks();
+        let (pic, bit) = (usize::from(line / 8), 1 << (line % 8));
+        if masked {
+            masks[pic] |= bit;
+        } else {
+            masks[pic] &= !bit;
+        }
+        pics.write_masks(masks[0], masks[1]);
+    }
+}
+
+/// Runs the handlers of `vector`, signals the end of the interrupt and runs the work they
+/// deferred.
+fn dispatch(vector: u8) {
+    let interrupt = percpu::enter_interrupt();
+    stats::record(vector);
+    let mut handlers = HANDLERS[usize::from(vector)].lock();
+    if handlers.is_empty() {
+        println!(
+            "unexpected interrupt on vector {vector:#x} ({}) on CPU {}",
+            stats::vector_name(vector),
+            percpu::cpu_id()
+        );
+    }
+    // a shared line may have been raised by several devices at once, so all handlers run
+    let handled = handlers.iter_mut().fold(false, |handled, handler| {
+        (handler.handler)() == IrqReturn::Handled || handled
+    });
+    drop(handlers);
+    if !handled {
+        UNHANDLED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    match legacy_line(vector) {
+        // # Safety
+        // the vector belongs to the PICs
+        Some(_) => unsafe {
+            PICS.get().unwrap().lock().notify_end_of_interrupt(vector);
+        },
+        None => apic::end_of_interrupt(),
+    }
+    drop(interrupt);
+    work::run_pending();
+}
+
+/// Returns whether the interrupt of `line`, one of [`SPURIOUS_LINES`], is spurious and signals
+/// the end of the cascade interrupt to the first PIC for a spurious interrupt of the second one.
+///
+/// # Panics
+/// The function will panic if the PICs are not initialized.
+fn is_spurious(line: u8) -> bool {
+    // the lock keeps other processors from accessing the PICs in between
+    let _pics = PICS.get().unwrap().lock();
+    let command = if line < 8 {
+        PIC_1_COMMAND
+    } else {
+        PIC_2_COMMAND
+    };
+    // # Safety
+    // reading the in-service register does not change the state of the PIC
+    let in_service: u8 = unsafe {
+        Port::new(command).write(READ_IN_SERVICE);
+        Port::new(command).read()
+    };
+    if in_service & 1 << (line % 8) != 0 {
+        return false;
+    }
+    if line >= 8 {
+        // # Safety
+        // the first PIC has the cascade line in service
+        unsafe { Port::new(PIC_1_COMMAND).write(END_OF_INTERRUPT) };
+    }
+    true
+}
+
+extern "x86-interrupt" fn spurious_line_stub<const LINE: u8>(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    if !is_spurious(LINE) {
+        dispatch(FIRST_VECTOR + LINE);
+        return;
+    }
+    let _interrupt = percpu::enter_interrupt();
+    stats::record(FIRST_VECTOR + LINE);
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "x86-interrupt" fn irq_stub<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter(&stack_frame);
+    dispatch(VECTOR);
+}
+
+/// Generates the stubs of 16 vectors starting at `$base`.
+macro_rules! stubs {
+    ($($base:literal),*) => {
+        [$(
+            irq_stub::<{ $base }>,
+            irq_stub::<{ $base + 1 }>,
+            irq_stub::<{ $base + 2 }>,
+            irq_stub::<{ $base + 3 }>,
+            irq_stub::<{ $base + 4 }>,
+            irq_stub::<{ $base + 5 }>,
+            irq_stub::<{ $base + 6 }>,
+            irq_stub::<{ $base + 7 }>,
+            irq_stub::<{ $base + 8 }>,
+            irq_stub::<{ $base + 9 }>,
+            irq_stub::<{ $base + 10 }>,
+            irq_stub::<{ $base + 11 }>,
+            irq_stub::<{ $base + 12 }>,
+            irq_stub::<{ $base + 13 }>,
+            irq_stub::<{ $base + 14 }>,
+            irq_stub::<{ $base + 15 }>,
+        )*]
+    };
+}
+
+/// The stubs of the vectors from [`FIRST_VECTOR`] to the local APIC timer vector, which does
+/// not use its stub.
+static STUBS: [extern "x86-interrupt" fn(InterruptStackFrame); 208] =
+    stubs!(0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80, 0x90, 0xa0, 0xb0, 0xc0, 0xd0, 0xe0);
@@ -0,0 +1,182 @@
+//! Machine check architecture.
+//!
+//! The processor reports hardware errors, e.g. uncorrected memory or bus errors, in its banks of
+//! error reporting registers and raises a machine check exception for the fatal ones. The
+//! handler logs all valid banks and panics, there is no recovery from machine checks yet.
+
+use super::stats;
+use crate::{percpu, println};
+use core::arch::x86_64::__cpuid;
+use core::fmt;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::InterruptStackFrame;
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MCG_CTL: u32 = 0x17b;
+/// The registers of bank `i` start at `IA32_MC0_CTL + 4 * i`
+const IA32_MC0_CTL: u32 = 0x400;
+const STATUS: u32 = 1;
+const ADDR: u32 = 2;
+const MISC: u32 = 3;
+
+// CPUID.01H:EDX
+const MCE: u32 = 1 << 7;
+const MCA: u32 = 1 << 14;
+
+// IA32_MCG_CAP
+const BANK_COUNT: u64 = 0xff;
+const MCG_CTL_PRESENT: u64 = 1 << 8;
+
+// IA32_MCG_STATUS
+const RESTART_IP_VALID: u64 = 1 << 0;
+const ERROR_IP_VALID: u64 = 1 << 1;
+
+// IA32_MCi_STATUS
+const VALID: u64 = 1 << 63;
+const OVERFLOW: u64 = 1 << 62;
+const UNCORRECTED: u64 = 1 << 61;
+const ENABLED: u64 = 1 << 60;
+const MISC_VALID: u64 = 1 << 59;
+const ADDR_VALID: u64 = 1 << 58;
+const CONTEXT_CORRUPT: u64 = 1 << 57;
+
+/// Enables machine check exceptions for all error reporting banks of the current processor, if
+/// it supports them.
+///
+/// Errors left in the banks from before, e.g. by a previous boot, are logged and cleared.
+pub(super) fn init_cpu() {
+    // # Safety
+    // CPUID is available on every x86_64 CPU
+    if unsafe { __cpuid(1) }.edx & MCE == 0 {
+        return;
+    }
+    if supports_mca() {
+        // # Safety
+        // the MSRs exist on every CPU supporting MCA, enabling the reporting of all errors has
+        // no side effects besides the exceptions
+        unsafe {
+            let capabilities = Msr::new(IA32_MCG_CAP).read();
+            if capabilities & MCG_CTL_PRESENT != 0 {
+                Msr::new(IA32_MCG_CTL).write(u64::MAX);
+            }
+            for bank in 0..bank_count() {
+                if let Some(error) = BankError::read(bank) {
+                    println!(
+                        "machine check error left from before on CPU {}\n{error}",
+                        percpu::cpu_id()
+                    );
+                }
+                Msr::new(bank_register(bank, STATUS)).write(0);
+                // the first bank is configured by the firmware on some processors
+                if bank > 0 {
+                    Msr::new(IA32_MC0_CTL + 4 * bank).write(u64::MAX);
+                }
+            }
+        }
+    }
+    // # Safety
+    // the handler is installed in the IDT
+    unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+}
+
+pub(super) extern "x86-interrupt" fn machine_check_handler(frame: InterruptStackFrame) -> ! {
+    let _gs = percpu::KernelGsGuard::enter_paranoid();
+    stats::record(18);
+    if !supports_mca() {
+        panic!("Exception: machine check\n{:#?}", frame);
+    }
+    // # Safety
+    // the MSR exists on every CPU supporting MCA
+    let status = unsafe { Msr::new(IA32_MCG_STATUS).read() };
+    for bank in 0..bank_count() {
+        if let Some(error) = BankError::read(bank) {
+            println!("machine check {error}");
+        }
+    }
+    panic!(
+        "Exception: machine check\n\
+         restart ip valid: {}, error ip valid: {}\n\
+         {:#?}",
+        status & RESTART_IP_VALID != 0,
+        status & ERROR_IP_VALID != 0,
+        frame
+    );
+}
+
+fn supports_mca() -> bool {
+    // # Safety
+    // CPUID is available on every x86_64 CPU
+    unsafe { __cpuid(1) }.edx & MCA != 0
+}
+
+/// Returns the number of error reporting banks of a CPU supporting MCA.
+fn bank_count() -> u32 {
+    // # Safety
+    // the MSR exists on every CPU supporting MCA
+    (unsafe { Msr::new(IA32_MCG_CAP).read() } & BANK_COUNT) as u32
+}
+
+fn bank_register(bank: u32, register: u32) -> u32 {
+    IA32_MC0_CTL + 4 * bank + register
+}
+
+/// The error reported by an error reporting bank.
+struct BankError {
+    bank: u32,
+    status: u64,
+    addr: Option<u64>,
+    misc: Option<u64>,
+}
+
+impl BankError {
+    /// Reads the bank, returns [`None`] if it does not hold a valid error.
+    fn read(bank: u32) -> Option<Self> {
+        // # Safety
+        // the bank exists, reading its registers has no side effects
+        unsafe {
+            let status = Msr::new(bank_register(bank, STATUS)).read();
+            if status & VALID == 0 {
+                return None;
+            }
+            Some(Self {
+                bank,
+                status,
+                addr: (status & ADDR_VALID != 0)
+                    .then(|| Msr::new(bank_register(bank, ADDR)).read()),
+                misc: (status & MISC_VALID != 0)
+                    .then(|| Msr::new(bank_register(bank, MISC)).read()),
+            })
+        }
+    }
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bank {}: status {:#018x}, error code {:#06x}, model specific code {:#06x}",
+            self.bank,
+            self.status,
+            self.status & 0xffff,
+            self.status >> 16 & 0xffff
+        )?;
+        let flags = [
+            (UNCORRECTED, "uncorrected"),
+            (CONTEXT_CORRUPT, "processor context corrupt"),
+            (OVERFLOW, "overflow"),
+            (ENABLED, "enabled"),
+        ];
+        for (_, name) in flags.iter().filter(|(flag, _)| self.status & flag != 0) {
+            write!(f, ", {name}")?;
+        }
+        if let Some(addr) = self.addr {
+            write!(f, "\n  address {addr:#x}")?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, "\n  misc {misc:#x}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Non-maskable interrupts.
+//!
+//! The NMI handler runs on its own interrupt stack, as an NMI may arrive at any instruction,
+//! including the first ones of another handler. It runs the handlers registered with
+//! [`register`], e.g. for a watchdog or a profiler, until one of them claims the NMI. An
+//! unclaimed NMI is checked for the hardware errors reported through system control port B.
+//!
+//! NMI handlers cannot be masked, so they must not wait for locks. They must not fault either,
+//! the return from the fault would allow another NMI on the same stack.
+
+use super::stats;
+use crate::{percpu, println, smp};
+use core::mem;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Maximum number of registered NMI handlers.
+const MAX_HANDLERS: usize = 4;
+
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+const IO_CHANNEL_CHECK: u8 = 1 << 6;
+const SYSTEM_ERROR: u8 = 1 << 7;
+
+// only used to initialize the array of handlers
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Address of every registered handler, zero for a free slot
+static HANDLERS: [AtomicUsize; MAX_HANDLERS] = [NO_HANDLER; MAX_HANDLERS];
+static UNKNOWN: AtomicU64 = AtomicU64::new(0);
+
+/// Handles an NMI, returns whether it was the reason of the NMI.
+pub type NmiHandler = fn(&InterruptStackFrame) -> bool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmiError {
+    /// All [`MAX_HANDLERS`] slots are in use
+    TooManyHandlers,
+}
+
+/// Adds `handler` to the handlers run on every NMI.
+pub fn register(handler: NmiHandler) -> Result<(), NmiError> {
+    HANDLERS
+        .iter()
+        .find(|slot| {
+            slot.compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })
+        .map(|_| ())
+        .ok_or(NmiError::TooManyHandlers)
+}
+
+/// Removes `handler`, it may still run on an NMI in progress on another processor.
+pub fn unregister(handler: NmiHandler) {
+    for slot in &HANDLERS {
+        let _ = slot.compare_exchange(handler as usize, 0, Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+/// Returns the number of NMIs which no handler claimed.
+pub fn unknown() -> u64 {
+    UNKNOWN.load(Ordering::Relaxed)
+}
+
+pub(super) extern "x86-interrupt" fn nmi_handler(frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGsGuard::enter_paranoid();
+    let _interrupt = percpu::enter_interrupt();
+    stats::record(2);
+    // a panicking processor stops the others
+    smp::stop_if_requested();
+
+    // several sources may have raised the NMI at once
+    let handled = HANDLERS
+        .iter()
+        .map(|slot| slot.load(Ordering::Acquire))
+        .filter(|&handler| handler != 0)
+        .fold(false, |handled, handler| {
+            // # Safety
+            // only `NmiHandler`s are stored in the slots
+            let handler = unsafe { mem::transmute::<usize, NmiHandler>(handler) };
+            handler(&frame) || handled
+        });
+    if handled {
+        return;
+    }
+
+    // # Safety
+    // reading the status bits of port B has no side effects
+    let status: u8 = unsafe { Port::new(SYSTEM_CONTROL_PORT_B).read() };
+    let reason = if status & SYSTEM_ERROR != 0 {
+        "memory parity or system error"
+    } else if status & IO_CHANNEL_CHECK != 0 {
+        "I/O channel check"
+    } else {
+        "unknown reason"
+    };
+    UNKNOWN.fetch_add(1, Ordering::Relaxed);
+    // printing queues the output if this processor was interrupted while using the console
+    println!(
+        "NMI on CPU {} ({reason}) at {:#x}",
+        percpu::cpu_id(),
+        frame.instruction_pointer
+    );
+}
@@ -0,0 +1,126 @@
+//! Interrupt and exception counters.
+//!
+//! Every handler counts its vector on the processor it runs on. [`InterruptTable`] formats the
+//! counters like `/proc/interrupts`, one row for every vector that has occurred and one column
+//! for every processor.
+
+use super::{irq, InterruptIndex};
+use crate::percpu::{self, MAX_CPUS};
+use crate::smp;
+use alloc::format;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// only used to initialize the arrays of counters
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_COUNTS: [AtomicU64; 256] = [ZERO; 256];
+
+static COUNTS: [[AtomicU64; 256]; MAX_CPUS] = [NO_COUNTS; MAX_CPUS];
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "divide error",
+    "debug",
+    "non-maskable interrupt",
+    "breakpoint",
+    "overflow",
+    "bound range exceeded",
+    "invalid opcode",
+    "device not available",
+    "double fault",
+    "coprocessor segment overrun",
+    "invalid tss",
+    "segment not present",
+    "stack segment fault",
+    "general protection fault",
+    "page fault",
+    "reserved",
+    "x87 floating point",
+    "alignment check",
+    "machine check",
+    "simd floating point",
+    "virtualization",
+    "control protection",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "hypervisor injection",
+    "vmm communication",
+    "security",
+    "reserved",
+];
+
+/// Counts an occurrence of `vector` on the current processor.
+pub(super) fn record(vector: u8) {
+    COUNTS[percpu::cpu_id()][usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how often `vector` has occurred on the processor `cpu`.
+///
+/// # Panics
+/// The function will panic if `cpu` is not below [`MAX_CPUS`].
+pub fn count(cpu: usize, vector: u8) -> u64 {
+    COUNTS[cpu][usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/// Returns how often `vector` has occurred on all processors.
+pub fn total(vector: u8) -> u64 {
+    (0..MAX_CPUS).map(|cpu| count(cpu, vector)).sum()
+}
+
+/// Returns a description of what raises `vector`.
+pub fn vector_name(vector: u8) -> impl fmt::Display {
+    VectorName(vector)
+}
+
+/// The counters of all vectors which have occurred, formatted like `/proc/interrupts`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterruptTable;
+
+impl fmt::Display for InterruptTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpus = smp::cpus().len().max(1);
+        write!(f, "     ")?;
+        for cpu in 0..cpus {
+            write!(f, " {:>10}", format!("CPU{cpu}"))?;
+        }
+        writeln!(f)?;
+
+        for vector in (0..=u8::MAX).filter(|&vector| total(vector) > 0) {
+            write!(f, "{vector:>4}:")?;
+            for cpu in 0..cpus {
+                write!(f, " {:>10}", count(cpu, vector))?;
+            }
+            writeln!(f, "  {}", VectorName(vector))?;
+        }
+        Ok(())
+    }
+}
+
+struct VectorName(u8);
+
+impl fmt::Display for VectorName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vector = self.0;
+        if let Some(name) = EXCEPTION_NAMES.get(usize::from(vector)) {
+            return write!(f, "{name}");
+        }
+        let local_apic = [
+            InterruptIndex::LocalTimer,
+            InterruptIndex::CallFunction,
+            InterruptIndex::TlbShootdown,
+            InterruptIndex::Spurious,
+        ];
+        if let Some(index) = local_apic.iter().find(|&&index| u8::from(index) == vector) {
+            return write!(f, "{index:?}");
+        }
+        match vector.checked_sub(irq::FIRST_VECTOR) {
+            Some(line) if line < irq::IRQ_LINES => write!(f, "IRQ {line}"),
+            _ => write!(f, "vector {vector:#x}"),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Work deferred by interrupt handlers.
+//!
+//! Handlers should only acknowledge their device and leave everything else to a [`Work`] item,
+//! which runs at the tail of the interrupt after the end of interrupt has been signaled, with
+//! interrupts enabled. There are no kernel threads yet to run work on, so the items of a
+//! processor run at the end of its next interrupt that did not interrupt another handler or work
+//! item. Work items still count as interrupt context and must not wait for locks which are held
+//! with interrupts enabled.
+//!
+//! [`DelayedWork`] is scheduled after a delay measured by the timer ticks of the bootstrap
+//! processor.
+//!
+//! The items are static and linked into the queues, so scheduling them never allocates.
+
+use super::apic::TIMER_FREQUENCY;
+use crate::percpu::{self, MAX_CPUS};
+use crate::sync::IrqSpinLock;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+// only used to initialize the array of queues
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: AtomicPtr<Work> = AtomicPtr::new(ptr::null_mut());
+
+/// The work items scheduled on every processor, most recently scheduled first
+static QUEUES: [AtomicPtr<Work>; MAX_CPUS] = [EMPTY_QUEUE; MAX_CPUS];
+/// Delayed work items waiting for their deadline
+static TIMERS: IrqSpinLock<Option<&'static DelayedWork>> = IrqSpinLock::new(None);
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// A function run at the tail of an interrupt.
+#[derive(Debug)]
+pub struct Work {
+    function: fn(),
+    queued: AtomicBool,
+    next: AtomicPtr<Work>,
+}
+
+impl Work {
+    pub const fn new(function: fn()) -> Self {
+        Self {
+            function,
+            queued: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Queues the work on the current processor.
+    ///
+    /// Returns `false` if it is already queued, it runs only once then. The work may schedule
+    /// itself again while it runs.
+    pub fn schedule(&'static self) -> bool {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let queue = &QUEUES[percpu::cpu_id()];
+        let mut head = queue.load(Ordering::Relaxed);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match queue.compare_exchange_weak(
+                head,
+                self as *const Work as *mut Work,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+/// A [`Work`] item scheduled after a delay.
+#[derive(Debug)]
+pub struct DelayedWork {
+    work: Work,
+    /// Tick at which the work is scheduled
+    deadline: AtomicU64,
+    armed: AtomicBool,
+    next: AtomicPtr<DelayedWork>,
+}
+
+impl DelayedWork {
+    pub const fn new(function: fn()) -> Self {
+        Self {
+            work: Work::new(function),
+            deadline: AtomicU64::new(0),
+            armed: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Schedules the work on the bootstrap processor once at least `milliseconds` have passed.
+    ///
+    /// Returns `false` if it is already waiting for its deadline, which is left unchanged.
+    pub fn schedule(&'static self, milliseconds: u64) -> bool {
+        let delay = (milliseconds * u64::from(TIMER_FREQUENCY))
+            .div_ceil(1000)
+            .max(1);
+        let mut timers = TIMERS.lock();
+        if self.armed.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        // the tick in progress may be almost over
+        self.deadline.store(ticks() + delay + 1, Ordering::Relaxed);
+        let head = timers.map_or(ptr::null_mut(), |head| head as *const _ as *mut _);
+        self.next.store(head, Ordering::Relaxed);
+        *timers = Some(self);
+        true
+    }
+
+    /// Returns whether the work is waiting for its deadline or queued to run.
+    pub fn is_pending(&self) -> bool {
+        self.armed.load(Ordering::Relaxed) || self.work.is_queued()
+    }
+}
+
+/// Returns the number of timer ticks of the bootstrap processor since the timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Advances the time and schedules the delayed work whose deadline has passed, called by the
+/// timer interrupt handler of the bootstrap processor.
+pub(super) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut timers = TIMERS.lock();
+    let mut previous: Option<&'static DelayedWork> = None;
+    let mut current = *timers;
+    while let Some(delayed) = current {
+        // # Safety
+        // only static items are linked into the list
+        let next = unsafe { delayed.next.load(Ordering::Relaxed).as_ref() };
+        if delayed.deadline.load(Ordering::Relaxed) <= now {
+            match previous {
+                Some(previous) => previous.next.store(
+                    next.map_or(ptr::null_mut(), |next| next as *const _ as *mut _),
+                    Ordering::Relaxed,
+                ),
+                None => *timers = next,
+            }
+            // the work is queued before it is disarmed, so it is pending throughout
+            delayed.work.schedule();
+            delayed.armed.store(false, Ordering::Relaxed);
+        } else {
+            previous = Some(delayed);
+        }
+        current = next;
+    }
+}
+
+/// Runs the work queued on the current processor with interrupts enabled, called at the tail of
+/// the interrupt handlers after the end of the interrupt.
+///
+/// Interrupts are disabled again when it returns.
+pub(super) fn run_pending() {
+    // an interrupt of another handler or work item leaves its work to them
+    if percpu::in_interrupt() {
+        return;
+    }
+    let _interrupt = percpu::enter_interrupt();
+    let queue = &QUEUES[percpu::cpu_id()];
+    loop {
+        // checked with interrupts disabled, so no work is left behind
+        let mut list = queue.swap(ptr::null_mut(), Ordering::Acquire);
+        if list.is_null() {
+            return;
+        }
+
+        // run the items in the order they were scheduled
+        let mut reversed: *mut Work = ptr::null_mut();
+        while !list.is_null() {
+            // # Safety
+            // only static items are linked into the queue, and the queue has been taken over
+            let work = unsafe { &*list };
+            list = work.next.load(Ordering::Relaxed);
+            work.next.store(reversed, Ordering::Relaxed);
+            reversed = work as *const Work as *mut Work;
+        }
+
+        interrupts::enable();
+        while !reversed.is_null() {
+            // # Safety
+            // see above
+            let work = unsafe { &*reversed };
+            reversed = work.next.load(Ordering::Relaxed);
+            work.queued.store(false, Ordering::Release);
+            (work.function)();
+        }
+        interrupts::disable();
+    }
+}
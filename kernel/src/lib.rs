@@ -1,17 +1,28 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(naked_functions)]
+#![cfg_attr(feature = "lockdep", feature(const_caller_location))]
+
+extern crate alloc;
 
 use bootloader_api::{config::Mapping, BootInfo, BootloaderConfig};
 
+pub mod acpi;
+pub mod allocator;
+pub mod backtrace;
+pub mod font;
 pub mod interrupt;
 pub mod logger;
 pub mod memory;
+pub mod percpu;
+pub mod smp;
+pub mod sync;
 pub mod vga;
 
 pub use bootloader_api;
 pub use x86_64;
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
 
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
@@ -20,13 +31,6 @@ pub static BOOTLOADER_CONFIG: BootloaderConfig = {
 };
 
 pub fn init(boot_info: &'static mut BootInfo) {
-    interrupt::init();
-
-    let framebuffer = boot_info.framebuffer.as_mut().unwrap();
-    let vga = vga::Writer::new(framebuffer);
-
-    logger::init_global(vga);
-
     let Some(physical_memory_offset) = boot_info
         .physical_memory_offset
         .into_option()
@@ -35,10 +39,61 @@ pub fn init(boot_info: &'static mut BootInfo) {
         panic!("physical_memory_offset in not present, make sure that `map-physical-memory` BootloaderConfig option is enabled");
     };
 
+    percpu::init(0);
+    interrupt::init_early();
     memory::init_global(physical_memory_offset, &boot_info.memory_regions);
+    allocator::init_heap().expect("failed to initialize the kernel heap");
+    memory::stack::register_current("main");
+    // allocating the interrupt stacks needs the memory manager and the heap
+    interrupt::init();
+
+    let ramdisk = ramdisk(boot_info);
+    let rsdp = boot_info.rsdp_addr.into_option().map(PhysAddr::new);
+    let framebuffer = boot_info.framebuffer.as_mut().unwrap();
+    let mut vga = vga::Writer::new(framebuffer);
+    vga.clear();
+    draw_boot_logo(&mut vga);
+    vga.present();
+
+    logger::init_global(vga);
+
+    if let Some(ramdisk) = ramdisk {
+        // the ramdisk is used as a console font if it contains one
+        if let Ok(font) = font::Psf2Font::parse(ramdisk) {
+            if logger::set_font(font::Font::Psf2(font)).is_err() {
+                println!("the font of the ramdisk is too large for the screen");
+            }
+        }
+    }
+
+    // a kernel without the other processors is still usable
+    if let Err(error) = smp::init(rsdp) {
+        println!("failed to start the application processors: {error:?}");
+    }
     interrupt::enable_interrupts();
 }
 
+/// Returns the contents of the ramdisk loaded by the bootloader, if there is one.
+fn ramdisk(boot_info: &BootInfo) -> Option<&'static [u8]> {
+    let addr = boot_info.ramdisk_addr.into_option()?;
+    let len = usize::try_from(boot_info.ramdisk_len).ok()?;
+    // # Safety
+    // The bootloader maps the ramdisk at `ramdisk_addr` and never reuses that memory
+    Some(unsafe { core::slice::from_raw_parts(addr as *const u8, len) })
+}
+
+/// Draws the boot splash image in the center of the screen.
+fn draw_boot_logo(vga: &mut vga::Writer) {
+    const BOOT_LOGO: &[u8] = include_bytes!("../../src/assets/bootsplash.bmp");
+
+    // the logo is purely cosmetic, booting should not fail because of it
+    if let Ok(logo) = vga::bmp::decode(BOOT_LOGO) {
+        let x = (vga.width() as isize - logo.width() as isize) / 2;
+        let y = (vga.height() as isize - logo.height() as isize) / 2;
+        vga.blit(&logo, x, y);
+    }
+}
+
 pub fn halt_loop() -> ! {
     loop {
         // halts until next interrupt
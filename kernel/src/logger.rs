@@ -1,11 +1,29 @@
+use crate::backtrace::Backtrace;
+use crate::font::Font;
+use crate::interrupt::apic;
+use crate::percpu::{self, MAX_CPUS};
+use crate::smp;
+use crate::sync::{IrqSpinLock, IrqSpinLockGuard};
 use crate::vga;
+use alloc::{string::String, vec, vec::Vec};
 use core::fmt;
-use noto_sans_mono_bitmap::{FontWeight, RasterHeight};
-use spin::{once::Once, Mutex};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::once::Once;
+use uart_16550::SerialPort;
 
-const FONT_WEIGHT: FontWeight = FontWeight::Regular;
-const RASTER_HEIGHT: RasterHeight = RasterHeight::Size16;
-const RASTER_WIDTH: usize = noto_sans_mono_bitmap::get_raster_width(FONT_WEIGHT, RASTER_HEIGHT);
+/// Number of lines kept above the visible screen.
+const SCROLLBACK_LINES: usize = 500;
+
+/// Size of the buffer for output produced while the console is locked.
+const PENDING_OUTPUT_SIZE: usize = 4096;
+
+const COM1_PORT: u16 = 0x3F8;
+
+/// Time the panic handler waits for the console if another processor did not stop.
+const PANIC_CONSOLE_TIMEOUT_MILLISECONDS: u64 = 100;
 
 #[macro_export]
 macro_rules! print {
@@ -18,76 +36,583 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Prints to the console.
+///
+/// The console is waited for with interrupts disabled on the current processor. Prints nested in
+/// a print on the same processor, e.g. from an NMI or from formatting the arguments, cannot wait
+/// for it. Their output is queued and written by the outer print before it releases the console.
+///
+/// Output printed before [`init_global`] is queued as well and written once the console exists.
+/// Printing must not start before the per-CPU data is initialized with [`percpu::init`].
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    // FIXME (temporary solution): disable interrupts while printing
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        LOGGER.get().unwrap().lock().write_fmt(args).unwrap();
-    })
+    let Some(logger) = LOGGER.get() else {
+        queue(args);
+        return;
+    };
+
+    match Console::lock(logger) {
+        Some(mut logger) => {
+            logger.write_fmt(args).unwrap();
+            logger.write_pending();
+            logger.render();
+        }
+        None => {
+            queue(args);
+            return;
+        }
+    }
+
+    // output queued by an NMI after the pending output was written but before the console got
+    // unlocked would otherwise wait for the next print
+    while HAS_PENDING.load(Ordering::Acquire) {
+        let Some(mut logger) = Console::try_lock(logger) else {
+            break;
+        };
+        logger.write_pending();
+        logger.render();
+    }
+}
+
+/// Adds output to the pending output, it is written by the next print that gets the console.
+fn queue(args: fmt::Arguments) {
+    use core::fmt::Write;
+    // the outer print may be interrupted while it holds the pending output
+    if let Some(mut pending) = PENDING.try_lock() {
+        // the pending output never fails, it truncates what does not fit
+        let _ = pending.write_fmt(args);
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Prints the panic message followed by a backtrace.
+///
+/// The other processors are stopped first. Then the code holding the console lock will never
+/// release it and the lock is forcibly taken. If a processor did not stop, the console is only
+/// waited for a while. If the kernel panics again while printing the panic message (e.g.
+/// because the console itself is broken) the message goes only to the serial port.
+pub fn print_panic(info: &PanicInfo) {
+    use core::fmt::Write;
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        let _ = writeln!(emergency_serial(), "{info}");
+        return;
+    }
+    // the console lock is forcibly taken below
+    #[cfg(feature = "lockdep")]
+    crate::sync::lockdep::disable();
+
+    let backtrace = Backtrace::capture();
+    let _ = writeln!(emergency_serial(), "{info}\n{backtrace}");
+    let stopped = smp::stop_other_cpus();
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    // prints of NMIs arriving while the panic message is printed are queued
+    CONSOLE_USERS[percpu::cpu_id()].store(true, Ordering::Relaxed);
+    let logger = if stopped {
+        // # Safety
+        // the current processor is the only one running, the previous lock holder will never run
+        // again
+        if logger.is_locked() {
+            unsafe { logger.force_unlock() };
+        }
+        Some(logger.lock())
+    } else {
+        // a processor still running may be writing to the console
+        (0..PANIC_CONSOLE_TIMEOUT_MILLISECONDS).find_map(|_| {
+            let logger = logger.try_lock();
+            if logger.is_none() {
+                apic::wait_microseconds(1000);
+            }
+            logger
+        })
+    };
+    if let Some(mut logger) = logger {
+        logger.write_pending();
+        let _ = writeln!(logger, "{info}\n{backtrace}");
+        logger.render();
+    }
+}
+
+/// Returns a serial port (COM1) writer that does not depend on any lock.
+fn emergency_serial() -> SerialPort {
+    // # Safety
+    // COM1 is used only for emergency output, interleaved writes garble the output at worst
+    let mut port = unsafe { SerialPort::new(COM1_PORT) };
+    port.init();
+    port
+}
+
+/// Scrolls the console one page back into the history.
+pub fn page_up() {
+    try_with_logger(|logger| logger.scroll_back(logger.rows()));
+}
+
+/// Scrolls the console one page forward towards the most recent output.
+pub fn page_down() {
+    try_with_logger(|logger| logger.scroll_forward(logger.rows()));
+}
+
+/// Switches the console to another font, the text is laid out again for the new cell size.
+///
+/// A font whose glyphs do not fit on the screen is rejected and the current font is kept.
+///
+/// Must not be called from interrupt handlers as it waits for the console.
+pub fn set_font(font: Font) -> Result<(), FontTooLarge> {
+    if let Some(mut logger) = LOGGER.get().and_then(Console::lock) {
+        logger.set_font(font)?;
+        logger.render();
+    }
+    Ok(())
+}
+
+/// The glyphs of a font are wider or taller than the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontTooLarge;
+
+/// Returns the text of the last `count` complete lines of the console, oldest first and without
+/// trailing blanks. Output still queued for the console is written first.
+///
+/// Must not be called from interrupt handlers as it waits for the console.
+pub fn recent_lines(count: usize) -> Vec<String> {
+    let Some(mut logger) = LOGGER.get().and_then(Console::lock) else {
+        return Vec::new();
+    };
+    logger.write_pending();
+    logger.render();
+    let complete = logger.line_count - 1;
+    (complete.saturating_sub(count)..complete)
+        .map(|line| logger.line_text(line))
+        .collect()
+}
+
+/// Returns the text of the lines currently shown on the screen, top to bottom and without
+/// trailing blanks.
+///
+/// Must not be called from interrupt handlers as it waits for the console.
+pub fn visible_lines() -> Vec<String> {
+    let Some(logger) = LOGGER.get().and_then(Console::lock) else {
+        return Vec::new();
+    };
+    let bottom = logger.line_count - logger.scrollback;
+    (bottom.saturating_sub(logger.rows)..bottom)
+        .map(|line| logger.line_text(line))
+        .collect()
+}
+
+/// Number of visible text rows of the console, 0 if it is not initialized yet.
+pub fn rows() -> usize {
+    LOGGER
+        .get()
+        .and_then(Console::lock)
+        .map_or(0, |logger| logger.rows())
+}
+
+/// Runs `f` if the console is not in use, the request is dropped otherwise.
+fn try_with_logger(f: impl FnOnce(&mut Logger<'static>)) {
+    if let Some(mut logger) = LOGGER.get().and_then(Console::try_lock) {
+        f(&mut logger);
+        logger.render();
+    }
+}
+
+/// The console locked by the current processor.
+///
+/// Code interrupting the lock holder on the same processor, e.g. an NMI, sees the console in use
+/// instead of waiting for it forever.
+struct Console {
+    logger: ManuallyDrop<IrqSpinLockGuard<'static, Logger<'static>>>,
+    cpu: usize,
+}
+
+impl Console {
+    /// Waits for the console, returns `None` if the current processor is already using it.
+    fn lock(logger: &'static IrqSpinLock<Logger<'static>>) -> Option<Self> {
+        Self::acquire(|| Some(logger.lock()))
+    }
+
+    /// Returns `None` if the console is in use.
+    fn try_lock(logger: &'static IrqSpinLock<Logger<'static>>) -> Option<Self> {
+        Self::acquire(|| logger.try_lock())
+    }
+
+    fn acquire(
+        lock: impl FnOnce() -> Option<IrqSpinLockGuard<'static, Logger<'static>>>,
+    ) -> Option<Self> {
+        let cpu = percpu::cpu_id();
+        // the flag is set before locking, an NMI arriving in between must not wait either
+        if CONSOLE_USERS[cpu].swap(true, Ordering::Acquire) {
+            return None;
+        }
+        match lock() {
+            Some(logger) => Some(Self {
+                logger: ManuallyDrop::new(logger),
+                cpu,
+            }),
+            None => {
+                CONSOLE_USERS[cpu].store(false, Ordering::Release);
+                None
+            }
+        }
+    }
+}
+
+impl Deref for Console {
+    type Target = Logger<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.logger
+    }
+}
+
+impl DerefMut for Console {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.logger
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        // # Safety
+        // the guard is not used after it has been dropped
+        unsafe { ManuallyDrop::drop(&mut self.logger) };
+        CONSOLE_USERS[self.cpu].store(false, Ordering::Release);
+    }
+}
+
+static LOGGER: Once<IrqSpinLock<Logger<'static>>> = Once::new();
+// only used to initialize the array of flags
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_USING_CONSOLE: AtomicBool = AtomicBool::new(false);
+/// Whether each processor holds or waits for the console
+static CONSOLE_USERS: [AtomicBool; MAX_CPUS] = [NOT_USING_CONSOLE; MAX_CPUS];
+
+static PENDING: IrqSpinLock<PendingOutput> = IrqSpinLock::new(PendingOutput::new());
+static HAS_PENDING: AtomicBool = AtomicBool::new(false);
+/// Number of prints that could not be queued, or were truncated
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Output produced while the console was locked or before it was initialized.
+struct PendingOutput {
+    buffer: [u8; PENDING_OUTPUT_SIZE],
+    len: usize,
 }
 
-static LOGGER: Once<Mutex<Logger<'static>>> = Once::new();
+impl PendingOutput {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; PENDING_OUTPUT_SIZE],
+            len: 0,
+        }
+    }
 
+    fn as_str(&self) -> &str {
+        // only whole characters are ever appended
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or_default()
+    }
+}
+
+impl fmt::Write for PendingOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let free = self.buffer.len() - self.len;
+        let mut len = s.len().min(free);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+        HAS_PENDING.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// The screen is expected to be cleared, anything already drawn stays visible until it gets
+/// covered by text.
+///
 /// # Panics
 /// The function will panic if it is called more than once.
 pub fn init_global(writer: vga::Writer<'static>) {
-    let mut logger = Logger::new(writer);
-    logger.clear();
-    LOGGER.call_once(|| Mutex::new(logger));
+    let logger = Logger::new(writer);
+    let logger = LOGGER.call_once(|| IrqSpinLock::new(logger));
+    // output printed before the console existed
+    if let Some(mut logger) = Console::lock(logger) {
+        logger.write_pending();
+        logger.render();
+    }
 }
 
+/// Foreground and background color of a single cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    pub foreground: vga::Color,
+    pub background: vga::Color,
+}
+
+impl Attributes {
+    pub const DEFAULT: Attributes = Attributes {
+        foreground: vga::Color::GREEN,
+        background: vga::Color::BLACK,
+    };
+}
+
+/// A single character position of the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub character: char,
+    pub attributes: Attributes,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell {
+        character: ' ',
+        attributes: Attributes::DEFAULT,
+    };
+}
+
+/// Text console backed by a grid of [`Cell`]s.
+///
+/// Lines are kept in a ring buffer which holds the visible screen and [`SCROLLBACK_LINES`] of
+/// history. Writing only updates the cells, [`Logger::render`] draws the cells that differ from
+/// what is currently displayed.
 #[derive(Debug)]
 pub struct Logger<'a> {
     writer: vga::Writer<'a>,
-    x: usize,
-    y: usize,
+    columns: usize,
+    rows: usize,
+    /// Ring buffer of `capacity` lines, each `columns` cells long
+    lines: Vec<Cell>,
+    capacity: usize,
+    /// Ring index of the oldest line
+    first_line: usize,
+    /// Number of lines in use, the cursor is always on the last one
+    line_count: usize,
+    column: usize,
+    /// Number of lines the view is scrolled back from the most recent output
+    scrollback: usize,
+    /// Cells currently drawn on the screen
+    screen: Vec<Cell>,
+    attributes: Attributes,
+    font: Font,
 }
 
 impl<'a> Logger<'a> {
     pub fn new(writer: vga::Writer<'a>) -> Self {
-        Self { writer, x: 0, y: 0 }
+        let font = Font::default();
+        let columns = writer.width() / font.glyph_width();
+        let rows = writer.height() / font.glyph_height();
+        let capacity = rows + SCROLLBACK_LINES;
+        Self {
+            writer,
+            columns,
+            rows,
+            lines: vec![Cell::BLANK; capacity * columns],
+            capacity,
+            first_line: 0,
+            line_count: 1,
+            column: 0,
+            scrollback: 0,
+            screen: vec![Cell::BLANK; rows * columns],
+            attributes: Attributes::DEFAULT,
+            font,
+        }
+    }
+
+    /// Replaces the font and lays out the history for the new grid size.
+    ///
+    /// Lines wider than the new grid are truncated. The font is not used if not even a single
+    /// glyph fits on the screen.
+    pub fn set_font(&mut self, font: Font) -> Result<(), FontTooLarge> {
+        let columns = self.writer.width() / font.glyph_width();
+        let rows = self.writer.height() / font.glyph_height();
+        if columns == 0 || rows == 0 {
+            return Err(FontTooLarge);
+        }
+        let capacity = rows + SCROLLBACK_LINES;
+
+        let mut lines = vec![Cell::BLANK; capacity * columns];
+        let line_count = self.line_count.min(capacity);
+        let skipped_lines = self.line_count - line_count;
+        for (line, new_line) in
+            (skipped_lines..self.line_count).zip(lines.chunks_exact_mut(columns))
+        {
+            let old_line = self.line(line);
+            let width = columns.min(old_line.len());
+            new_line[..width].copy_from_slice(&old_line[..width]);
+        }
+
+        self.font = font;
+        self.columns = columns;
+        self.rows = rows;
+        self.lines = lines;
+        self.capacity = capacity;
+        self.first_line = 0;
+        self.line_count = line_count;
+        self.column = self.column.min(columns);
+        self.scrollback = 0;
+        self.screen = vec![Cell::BLANK; rows * columns];
+        self.writer.clear();
+        Ok(())
+    }
+
+    /// Number of visible text rows.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Number of visible text columns.
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
     }
 
     pub fn newline(&mut self) {
-        self.y += RASTER_HEIGHT.val();
-        self.x = 0;
+        if self.line_count < self.capacity {
+            self.line_count += 1;
+        } else {
+            // the history is full, drop the oldest line
+            self.first_line = (self.first_line + 1) % self.capacity;
+        }
+        let last_line = self.line_count - 1;
+        self.line_mut(last_line).fill(Cell::BLANK);
+        self.column = 0;
     }
 
     pub fn clear(&mut self) {
-        self.x = 0;
-        self.y = 0;
+        self.lines.fill(Cell::BLANK);
+        self.screen.fill(Cell::BLANK);
+        self.first_line = 0;
+        self.line_count = 1;
+        self.column = 0;
+        self.scrollback = 0;
         self.writer.clear();
+        self.writer.present();
     }
 
     pub fn write_char(&mut self, c: char) {
+        // new output always brings the view back to the bottom
+        self.scrollback = 0;
+
         if c == '\n' {
             self.newline();
             return;
         }
 
         if c == '\r' {
-            self.x += 0;
             return;
         }
 
-        if self.x + RASTER_WIDTH >= self.writer.width() {
+        if self.column >= self.columns {
             self.newline();
         }
 
-        if self.y + RASTER_HEIGHT.val() > self.writer.height() {
-            self.y -= RASTER_HEIGHT.val();
-            self.writer.shift_up(RASTER_HEIGHT.val());
+        let cell = Cell {
+            character: c,
+            attributes: self.attributes,
+        };
+        let (last_line, column) = (self.line_count - 1, self.column);
+        self.line_mut(last_line)[column] = cell;
+        self.column += 1;
+    }
+
+    /// Writes the output queued while the console was locked.
+    fn write_pending(&mut self) {
+        use core::fmt::Write;
+
+        if !HAS_PENDING.load(Ordering::Acquire) {
+            return;
+        }
+        // copy the output out so that interrupts can queue more while it is being written
+        let mut output = PendingOutput::new();
+        if let Some(mut pending) = PENDING.try_lock() {
+            output.buffer[..pending.len].copy_from_slice(&pending.buffer[..pending.len]);
+            output.len = pending.len;
+            pending.len = 0;
+            HAS_PENDING.store(false, Ordering::Release);
+        }
+        let _ = self.write_str(output.as_str());
+
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let _ = writeln!(self, "[console: output lost {dropped} times]");
+        }
+    }
+
+    /// Moves the view `lines` lines back into the history.
+    pub fn scroll_back(&mut self, lines: usize) {
+        let max_scrollback = self.line_count.saturating_sub(self.rows);
+        self.scrollback = (self.scrollback + lines).min(max_scrollback);
+    }
+
+    /// Moves the view `lines` lines towards the most recent output.
+    pub fn scroll_forward(&mut self, lines: usize) {
+        self.scrollback = self.scrollback.saturating_sub(lines);
+    }
+
+    /// Draws every visible cell that changed since the last render.
+    pub fn render(&mut self) {
+        let bottom = self.line_count - self.scrollback;
+        let top = bottom.saturating_sub(self.rows);
+
+        for row in 0..self.rows {
+            let line = top + row;
+            for column in 0..self.columns {
+                let cell = if line < bottom {
+                    self.line(line)[column]
+                } else {
+                    Cell::BLANK
+                };
+                let index = row * self.columns + column;
+                if self.screen[index] != cell {
+                    self.draw_cell(row, column, cell);
+                    self.screen[index] = cell;
+                }
+            }
         }
+        self.writer.present();
+    }
+
+    fn draw_cell(&mut self, row: usize, column: usize, cell: Cell) {
+        let (width, height) = (self.font.glyph_width(), self.font.glyph_height());
+        let (x, y) = (column * width, row * height);
+        let Attributes {
+            foreground,
+            background,
+        } = cell.attributes;
 
-        let rc = noto_sans_mono_bitmap::get_raster(c, FONT_WEIGHT, RASTER_HEIGHT).unwrap();
-        for (i, row) in rc.raster().iter().enumerate() {
-            for (j, &pixel) in row.iter().enumerate() {
-                let color = vga::Color::new(0, pixel, 0);
-                self.writer.write_pixel(self.x + j, self.y + i, color);
+        let glyph = self.font.glyph(cell.character);
+        for i in 0..height {
+            for j in 0..width {
+                let color = vga::Color::mix(background, foreground, glyph.intensity(j, i));
+                self.writer.write_pixel(x + j, y + i, color);
             }
         }
-        self.x += RASTER_WIDTH
+    }
+
+    /// Returns the text of the `line`-th line without trailing blanks.
+    fn line_text(&self, line: usize) -> String {
+        let text: String = self.line(line).iter().map(|cell| cell.character).collect();
+        String::from(text.trim_end())
+    }
+
+    /// Returns the cells of the `line`-th line counting from the oldest one.
+    fn line(&self, line: usize) -> &[Cell] {
+        let start = (self.first_line + line) % self.capacity * self.columns;
+        &self.lines[start..start + self.columns]
+    }
+
+    fn line_mut(&mut self, line: usize) -> &mut [Cell] {
+        let start = (self.first_line + line) % self.capacity * self.columns;
+        &mut self.lines[start..start + self.columns]
     }
 }
 
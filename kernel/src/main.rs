@@ -17,6 +17,6 @@ fn main(boot_info: &'static mut BootInfo) -> ! {
 }
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{info}");
+    kernel::logger::print_panic(info);
     kernel::halt_loop();
 }
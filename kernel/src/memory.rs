@@ -1,25 +1,170 @@
+pub mod address_space;
+pub mod area;
+mod protection;
+pub mod stack;
+pub mod tlb;
+pub mod user;
+
+use crate::percpu;
+use crate::smp;
+use crate::sync::{SpinLock, SpinLockGuard};
+use address_space::{AddressSpace, TableWalker, COPY_ON_WRITE};
+use alloc::collections::BTreeMap;
+use area::{AreaError, Backing, VmArea, VmAreas};
 use bootloader_api::info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
-use spin::{Mutex, Once};
-use x86_64::structures::paging::mapper::MapToError;
+use core::arch::x86_64::__cpuid;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Once;
+use tlb::Shootdown;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::{MapToError, MappedFrame, TranslateResult};
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB, Translate,
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
+    PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
 };
 use x86_64::{structures::paging::PageTable, PhysAddr, VirtAddr};
 
 const PAGE_FRAME_SIZE: usize = 4096;
 
-pub static MEMORY_MANAGER: Once<Mutex<MemoryManager>> = Once::new();
+/// Start of the virtual memory region at which device memory is mapped.
+const DEVICE_MEMORY_START: u64 = 0x6666_0000_0000;
+
+/// Start of the virtual memory region of the buffers too large for the kernel heap.
+const BUFFER_MEMORY_START: u64 = 0x7777_0000_0000;
+
+/// End of the memory which is addressable in real mode.
+const REAL_MODE_MEMORY_END: u64 = 0x10_0000;
+
+/// Value of [`MemoryManagerLock::owner`] while the lock is free.
+const NO_OWNER: usize = usize::MAX;
+
+pub static MEMORY_MANAGER: Once<MemoryManagerLock> = Once::new();
 
 /// # Panics
 /// The function will panic if it is called more than once.
 pub fn init_global(physical_memory_offset: VirtAddr, memory_regions: &'static MemoryRegions) {
-    let manager = unsafe { MemoryManager::new(physical_memory_offset, memory_regions) };
-    MEMORY_MANAGER.call_once(|| Mutex::new(manager));
+    let mut manager = unsafe { MemoryManager::new(physical_memory_offset, memory_regions) };
+    manager
+        .protect_kernel(memory_regions)
+        .expect("failed to protect the kernel image");
+    user::enable_protection();
+    MEMORY_MANAGER.call_once(|| MemoryManagerLock {
+        manager: SpinLock::new(manager),
+        owner: AtomicUsize::new(NO_OWNER),
+    });
+}
+
+/// Reason why a page fault could not be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// The address does not belong to any area of the address space
+    SegmentationViolation,
+    /// The access is not permitted by the flags of the area or the page
+    AccessViolation,
+    /// No frame could be allocated for the page
+    OutOfMemory,
+}
+
+/// The lock of the [`MemoryManager`].
+///
+/// Translations removed while the lock is held are invalidated on all processors after it is
+/// released, so no processor waits for the others while holding it. Otherwise a processor which
+/// faults while another one waits for its shootdown could never get the lock.
+pub struct MemoryManagerLock {
+    manager: SpinLock<MemoryManager>,
+    /// Processor holding the lock
+    owner: AtomicUsize,
+}
+
+impl MemoryManagerLock {
+    #[track_caller]
+    pub fn lock(&self) -> MemoryManagerGuard<'_> {
+        self.guard(self.manager.lock())
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MemoryManagerGuard<'_>> {
+        Some(self.guard(self.manager.try_lock()?))
+    }
+
+    /// Locks the manager in an exception handler, returns [`None`] if the interrupted code of
+    /// the current processor holds the lock.
+    ///
+    /// The requests of other processors are handled while waiting, as interrupts are disabled.
+    #[track_caller]
+    pub fn lock_in_exception(&self) -> Option<MemoryManagerGuard<'_>> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            if self.is_held_by_current_cpu() {
+                return None;
+            }
+            smp::handle_pending_requests();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns whether the current processor holds the lock.
+    pub fn is_held_by_current_cpu(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == percpu::cpu_id()
+    }
+
+    fn guard<'a>(&'a self, guard: SpinLockGuard<'a, MemoryManager>) -> MemoryManagerGuard<'a> {
+        self.owner.store(percpu::cpu_id(), Ordering::Relaxed);
+        MemoryManagerGuard {
+            lock: self,
+            guard: ManuallyDrop::new(guard),
+        }
+    }
+}
+
+/// Releases the [`MemoryManagerLock`] and invalidates the removed translations when dropped.
+pub struct MemoryManagerGuard<'a> {
+    lock: &'a MemoryManagerLock,
+    guard: ManuallyDrop<SpinLockGuard<'a, MemoryManager>>,
+}
+
+impl Deref for MemoryManagerGuard<'_> {
+    type Target = MemoryManager;
+
+    fn deref(&self) -> &MemoryManager {
+        &self.guard
+    }
+}
+
+impl DerefMut for MemoryManagerGuard<'_> {
+    fn deref_mut(&mut self) -> &mut MemoryManager {
+        &mut self.guard
+    }
+}
+
+impl Drop for MemoryManagerGuard<'_> {
+    fn drop(&mut self) {
+        let shootdown = core::mem::take(&mut self.guard.shootdown);
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        // # Safety
+        // the guard is not used afterwards
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        shootdown.finish();
+    }
 }
 
 pub struct MemoryManager {
     mapper: OffsetPageTable<'static>,
     frame_allocator: BootInfoFrameAllocator,
+    areas: VmAreas,
+    /// Whether the CPU supports 1 GiB pages
+    gigantic_pages: bool,
+    /// Start of the next device memory mapping
+    next_device_memory: u64,
+    /// Start of the next buffer mapping
+    next_buffer_memory: u64,
+    /// Translations to invalidate on all processors once the lock is released
+    shootdown: Shootdown,
 }
 
 impl MemoryManager {
@@ -41,7 +186,225 @@ impl MemoryManager {
     ) -> Self {
         Self {
             mapper: unsafe { init_mapper(physical_memory_offset) },
-            frame_allocator: unsafe { BootInfoFrameAllocator::init(memory_regions) },
+            frame_allocator: unsafe {
+                BootInfoFrameAllocator::init(memory_regions, physical_memory_offset)
+            },
+            areas: VmAreas::default(),
+            gigantic_pages: supports_gigantic_pages(),
+            next_device_memory: DEVICE_MEMORY_START,
+            next_buffer_memory: BUFFER_MEMORY_START,
+            shootdown: Shootdown::new(),
+        }
+    }
+
+    /// Areas of the address space which are mapped on demand.
+    pub fn areas(&self) -> &VmAreas {
+        &self.areas
+    }
+
+    /// Reserves a virtual memory region without mapping it.
+    ///
+    /// The pages are mapped with `flags` when they are accessed for the first time and filled
+    /// according to `backing`.
+    pub fn reserve_memory_region(
+        &mut self,
+        region_start: VirtAddr,
+        region_size: usize,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> Result<(), AreaError> {
+        self.areas.insert(VmArea {
+            start: region_start,
+            size: region_size as u64,
+            flags: flags | PageTableFlags::PRESENT,
+            backing,
+        })
+    }
+
+    /// Maps the page containing `addr` if it belongs to an area and the access described by
+    /// `error_code` is permitted.
+    ///
+    /// Returns [`Ok`] if the faulting access can be retried.
+    pub fn handle_page_fault(
+        &mut self,
+        addr: VirtAddr,
+        error_code: PageFaultErrorCode,
+    ) -> Result<(), PageFaultError> {
+        let Some(&area) = self.areas.find(addr) else {
+            // e.g. writing to the kernel's code
+            if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+                return Err(PageFaultError::AccessViolation);
+            }
+            return Err(PageFaultError::SegmentationViolation);
+        };
+
+        let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+        let fetch = error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH);
+        let user = error_code.contains(PageFaultErrorCode::USER_MODE);
+        if (write && !area.flags.contains(PageTableFlags::WRITABLE))
+            || (fetch && area.flags.contains(PageTableFlags::NO_EXECUTE))
+            || (user && !area.flags.contains(PageTableFlags::USER_ACCESSIBLE))
+        {
+            return Err(PageFaultError::AccessViolation);
+        }
+
+        let page = Page::<Size4KiB>::containing_address(addr);
+        if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            return if write {
+                self.copy_on_write(page, area.flags)
+            } else {
+                Err(PageFaultError::AccessViolation)
+            };
+        }
+
+        let page_offset = page.start_address() - area.start;
+        let frame = match area.backing {
+            Backing::Device { address } => PhysFrame::containing_address(address + page_offset),
+            Backing::Zero | Backing::File { .. } => {
+                let frame = self
+                    .frame_allocator
+                    .allocate_frame()
+                    .ok_or(PageFaultError::OutOfMemory)?;
+                // # Safety
+                // the frame has just been allocated and is accessed through the physical memory
+                // mapping only
+                let contents = unsafe {
+                    core::slice::from_raw_parts_mut(
+                        (self.mapper.phys_offset() + frame.start_address().as_u64())
+                            .as_mut_ptr::<u8>(),
+                        PAGE_FRAME_SIZE,
+                    )
+                };
+                contents.fill(0);
+                if let Backing::File { data, offset } = area.backing {
+                    let start = (offset as u64).saturating_add(page_offset) as usize;
+                    let data = data.get(start..).unwrap_or_default();
+                    let len = data.len().min(PAGE_FRAME_SIZE);
+                    contents[..len].copy_from_slice(&data[..len]);
+                }
+                frame
+            }
+        };
+
+        // # Safety
+        // the page belongs to an area, so it is not used for anything else, and it is not mapped
+        // yet as the fault was not a protection violation
+        unsafe {
+            self.mapper
+                .map_to(page, frame, area.flags, &mut self.frame_allocator)
+                .map_err(|_| PageFaultError::OutOfMemory)?
+                .flush();
+        }
+        Ok(())
+    }
+
+    /// Virtual address at which the complete physical memory is mapped.
+    pub fn physical_memory_offset(&self) -> VirtAddr {
+        self.mapper.phys_offset()
+    }
+
+    /// Allocates a frame which is not mapped anywhere but in the physical memory mapping.
+    pub fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.frame_allocator.allocate_frame()
+    }
+
+    /// Returns a frame from [`MemoryManager::allocate_frame`] to the frame allocator.
+    ///
+    /// # Safety
+    /// The frame must not be used after it is deallocated.
+    pub unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.frame_allocator.deallocate_frame(frame);
+    }
+
+    /// Maps `size` bytes of device memory at `start` uncached and returns their virtual address.
+    pub fn map_device_memory(
+        &mut self,
+        start: PhysAddr,
+        size: usize,
+    ) -> Result<VirtAddr, MapToError<Size4KiB>> {
+        let first = PhysFrame::<Size4KiB>::containing_address(start);
+        let last = PhysFrame::containing_address(start + size.max(1) as u64 - 1u64);
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH
+            | PageTableFlags::NO_EXECUTE;
+
+        let base = VirtAddr::new(self.next_device_memory);
+        for (index, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+            let page = Page::containing_address(base + index as u64 * Size4KiB::SIZE);
+            // # Safety
+            // the device memory region is used for nothing else and the frame is not RAM
+            unsafe {
+                self.mapper
+                    .map_to(page, frame, flags, &mut self.frame_allocator)?
+                    .flush();
+            }
+        }
+        self.next_device_memory += (last - first + 1) * Size4KiB::SIZE;
+        Ok(base + (start - first.start_address()))
+    }
+
+    /// Maps `size` bytes of zeroed memory for a buffer which may not fit into the kernel heap and
+    /// returns its address. The buffers are never freed.
+    pub fn allocate_buffer(&mut self, size: usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+        let base = VirtAddr::new(self.next_buffer_memory);
+        let size = size.max(1);
+        self.allocate_frames_for_memory_region(
+            base,
+            size,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )?;
+        // every buffer starts on a huge page boundary, so large buffers are mapped with them
+        self.next_buffer_memory += (size as u64).next_multiple_of(Size2MiB::SIZE);
+        // # Safety
+        // the memory has just been mapped and is used by nothing else
+        unsafe { core::ptr::write_bytes(base.as_mut_ptr::<u8>(), 0, size) };
+        Ok(base)
+    }
+
+    /// Returns a frame below 1 MiB for code which runs in real mode, like the startup code of
+    /// the application processors.
+    ///
+    /// The bootloader reports the memory below 1 MiB as used, as its real mode stages are loaded
+    /// there, but they are not needed after boot and the frame allocator never hands it out.
+    pub fn real_mode_frame(&self) -> Option<PhysFrame> {
+        let frame_size = PAGE_FRAME_SIZE as u64;
+        self.frame_allocator
+            .memory_regions
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Bootloader)
+            .find_map(|region| {
+                // the first frame holds the real mode interrupt vector table
+                let start = (region.start.max(frame_size) + frame_size - 1) & !(frame_size - 1);
+                (start + frame_size <= region.end.min(REAL_MODE_MEMORY_END))
+                    .then(|| PhysFrame::containing_address(PhysAddr::new(start)))
+            })
+    }
+
+    /// Maps `frame` at the virtual address matching its physical address.
+    ///
+    /// # Safety
+    /// The virtual address must not be used for anything else until
+    /// [`MemoryManager::identity_unmap`] is called.
+    pub unsafe fn identity_map(
+        &mut self,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        self.mapper
+            .identity_map(frame, flags, &mut self.frame_allocator)?
+            .flush();
+        Ok(())
+    }
+
+    /// Removes a mapping created by [`MemoryManager::identity_map`].
+    pub fn identity_unmap(&mut self, frame: PhysFrame) {
+        let page =
+            Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        if let Ok((_, flush)) = self.mapper.unmap(page) {
+            flush.ignore();
+            self.shootdown.add(page);
         }
     }
 
@@ -51,6 +414,129 @@ impl MemoryManager {
         self.mapper.translate_addr(addr)
     }
 
+    /// Gives the page written to by the faulting access a private copy of its frame.
+    fn copy_on_write(
+        &mut self,
+        page: Page<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), PageFaultError> {
+        let TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags: page_flags,
+            ..
+        } = self.mapper.translate(page.start_address())
+        else {
+            return Err(PageFaultError::AccessViolation);
+        };
+        if !page_flags.contains(COPY_ON_WRITE) {
+            return Err(PageFaultError::AccessViolation);
+        }
+
+        // the last owner can write to the frame directly
+        if !self.frame_allocator.is_shared(frame) {
+            // # Safety
+            // the page is only made writable as its area allows it
+            unsafe {
+                self.mapper
+                    .update_flags(page, flags)
+                    .map_err(|_| PageFaultError::AccessViolation)?
+                    .ignore();
+            }
+            // other processors would fault on their read-only translations
+            self.shootdown.add(page);
+            return Ok(());
+        }
+
+        let copy = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(PageFaultError::OutOfMemory)?;
+        let physical_memory_offset = self.mapper.phys_offset();
+        // # Safety
+        // both frames are accessed through the physical memory mapping and the copy has just
+        // been allocated
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                (physical_memory_offset + frame.start_address().as_u64()).as_ptr::<u8>(),
+                (physical_memory_offset + copy.start_address().as_u64()).as_mut_ptr::<u8>(),
+                PAGE_FRAME_SIZE,
+            );
+        }
+        self.frame_allocator.release(frame);
+
+        // # Safety
+        // the page is remapped to a copy of its contents
+        unsafe {
+            self.mapper
+                .unmap(page)
+                .map_err(|_| PageFaultError::AccessViolation)?
+                .1
+                .ignore();
+            self.mapper
+                .map_to(page, copy, flags, &mut self.frame_allocator)
+                .map_err(|_| PageFaultError::OutOfMemory)?
+                .ignore();
+        }
+        // other processors would still read the shared frame
+        self.shootdown.add(page);
+        Ok(())
+    }
+
+    /// Creates a copy of the active address space.
+    ///
+    /// The pages of the memory areas are shared copy-on-write, so the two address spaces diverge
+    /// only when one of them writes to its memory. Kernel memory outside of the areas is shared.
+    /// Mappings created later outside of the areas are not guaranteed to be visible in both
+    /// address spaces if they share page tables with an area.
+    ///
+    /// This is only the memory part of `fork`. The kernel has no system call entry and no
+    /// processes that could return into the child yet, so the `fork` system call is left for
+    /// when those exist. Until then callers switch to the child with
+    /// [`MemoryManager::switch_address_space`] themselves.
+    pub fn fork(&mut self) -> Result<AddressSpace, MapToError<Size4KiB>> {
+        let (level_4_table, _) = Cr3::read();
+        let mut walker = TableWalker {
+            physical_memory_offset: self.mapper.phys_offset(),
+            areas: &self.areas,
+            frame_allocator: &mut self.frame_allocator,
+        };
+        let child = walker.clone_table(level_4_table, 4, 0)?;
+        // the pages of the active address space have been made read-only
+        self.shootdown.add_all();
+
+        Ok(AddressSpace {
+            level_4_table: child,
+            areas: self.areas.clone(),
+        })
+    }
+
+    /// Activates `address_space` and returns the previously active address space.
+    ///
+    /// # Safety
+    /// There must be no references to the memory areas of the active address space, they would
+    /// point to the memory of `address_space` afterwards.
+    pub unsafe fn switch_address_space(&mut self, address_space: AddressSpace) -> AddressSpace {
+        let (level_4_table, flags) = Cr3::read();
+        let physical_memory_offset = self.mapper.phys_offset();
+        Cr3::write(address_space.level_4_table, flags);
+        self.mapper = init_mapper(physical_memory_offset);
+
+        AddressSpace {
+            level_4_table,
+            areas: core::mem::replace(&mut self.areas, address_space.areas),
+        }
+    }
+
+    /// Releases the memory of an inactive address space.
+    pub fn free_address_space(&mut self, address_space: AddressSpace) {
+        let mut walker = TableWalker {
+            physical_memory_offset: self.mapper.phys_offset(),
+            areas: &address_space.areas,
+            frame_allocator: &mut self.frame_allocator,
+        };
+        walker.free_table(address_space.level_4_table, 4, 0);
+    }
+
     /// Allocates frames for virtual memory region.
     /// * `region_start` - virtual address at which the region starts
     /// * `region_size` - size of the region
@@ -58,6 +544,9 @@ impl MemoryManager {
     ///
     /// [`MapToError`] determines whether an error occurred during frame allocation or page
     /// mapping.
+    ///
+    /// Parts of the region are mapped with 2 MiB and, if the CPU supports them, 1 GiB pages
+    /// wherever their alignment and size and the available physical memory allow it.
     /// # Example
     /// ```
     ///let memory_namager = MEMORY_MANAGER.get().unwrap();
@@ -77,17 +566,30 @@ impl MemoryManager {
         flags: PageTableFlags,
     ) -> Result<(), MapToError<Size4KiB>> {
         // Calculate which pages contain addresses from given memory region
-        // and createa range of that pages
-        let page_range = {
-            let region_start = VirtAddr::new(region_start.as_u64());
-            // Substract 1 to get inclusive bound
-            let region_end = region_start + region_size - 1u64;
-            let region_start_page = Page::containing_address(region_start);
-            let region_end_page = Page::containing_address(region_end);
-            Page::range_inclusive(region_start_page, region_end_page)
-        };
+        let mut page = Page::<Size4KiB>::containing_address(region_start);
+        // Substract 1 to get inclusive bound
+        let region_end_page = Page::containing_address(region_start + region_size - 1u64);
+
+        while page <= region_end_page {
+            let remaining = (region_end_page - page + 1) * Size4KiB::SIZE;
+
+            // Use the largest page size the alignment of the page and the remaining size allow
+            if self.gigantic_pages
+                && page.start_address().is_aligned(Size1GiB::SIZE)
+                && remaining >= Size1GiB::SIZE
+                && self.map_huge_page::<Size1GiB>(page.start_address(), flags)?
+            {
+                page += Size1GiB::SIZE / Size4KiB::SIZE;
+                continue;
+            }
+            if page.start_address().is_aligned(Size2MiB::SIZE)
+                && remaining >= Size2MiB::SIZE
+                && self.map_huge_page::<Size2MiB>(page.start_address(), flags)?
+            {
+                page += Size2MiB::SIZE / Size4KiB::SIZE;
+                continue;
+            }
 
-        for page in page_range {
             // Allocate avalible USABLE memory frame
             let frame = self
                 .frame_allocator
@@ -105,10 +607,66 @@ impl MemoryManager {
                     .map_to(page, frame, flags, &mut self.frame_allocator)?
                     .flush()
             };
+            page += 1;
         }
 
         Ok(())
     }
+
+    /// Maps a huge page at `start` to physically contiguous frames.
+    ///
+    /// Returns `false` if there are no suitable frames or the page overlaps existing mappings,
+    /// the caller then falls back to smaller pages.
+    fn map_huge_page<S: PageSize + core::fmt::Debug>(
+        &mut self,
+        start: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<bool, MapToError<Size4KiB>>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
+        let Some(frame) = self.frame_allocator.allocate_contiguous::<S>() else {
+            return Ok(false);
+        };
+        // # Safety
+        // the frames have just been allocated and the page is not mapped, otherwise an error is
+        // returned
+        let result = unsafe {
+            self.mapper.map_to(
+                Page::<S>::containing_address(start),
+                frame,
+                flags,
+                &mut self.frame_allocator,
+            )
+        };
+        match result {
+            Ok(flush) => {
+                flush.flush();
+                Ok(true)
+            }
+            Err(MapToError::FrameAllocationFailed) => {
+                self.frame_allocator.deallocate_contiguous(frame);
+                Err(MapToError::FrameAllocationFailed)
+            }
+            Err(_) => {
+                self.frame_allocator.deallocate_contiguous(frame);
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Returns whether the CPU supports 1 GiB pages, 2 MiB pages are always supported in long mode.
+fn supports_gigantic_pages() -> bool {
+    const EXTENDED_FEATURES: u32 = 0x8000_0001;
+    const PAGE_1GB: u32 = 1 << 26;
+
+    // # Safety
+    // CPUID is available on every x86_64 CPU
+    unsafe {
+        __cpuid(0x8000_0000).eax >= EXTENDED_FEATURES
+            && __cpuid(EXTENDED_FEATURES).edx & PAGE_1GB != 0
+    }
 }
 
 /// Initialize a new OffsetPageTable which allows for mapping virtual pages to the physical memory
@@ -120,7 +678,8 @@ impl MemoryManager {
 /// complete physical memory is mapped to virtual memory at the passed
 /// `physical_memory_offset`.
 ///
-/// This function must be called only once to avoid aliasing `&mut` references (which is undefined behavior).
+/// There must be no other mapper for the active level 4 table to avoid aliasing `&mut` references
+/// (which is undefined behavior).
 unsafe fn init_mapper(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
@@ -134,10 +693,9 @@ unsafe fn init_mapper(physical_memory_offset: VirtAddr) -> OffsetPageTable<'stat
 /// complete physical memory is mapped to virtual memory at the passed
 /// `physical_memory_offset`.
 ///
-/// This function must be called only once to avoid aliasing `&mut` references (which is undefined behavior).
+/// There must be no other reference to the active level 4 table to avoid aliasing `&mut`
+/// references (which is undefined behavior).
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
-
     // Get the frame where the Level 4 table is stored
     // That table occupies whole frame
     let (level_4_table_frame, _) = Cr3::read();
@@ -153,9 +711,30 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
 }
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
+///
+/// Frames are reference counted so they can be mapped by several address spaces. Frames with a
+/// single owner are not tracked, deallocated frames are reused before new ones are taken from the
+/// memory map.
+///
+/// Free frames are kept in a list of ranges stored in the free frames themselves, so the
+/// allocator works before the heap is initialized.
 struct BootInfoFrameAllocator {
     memory_regions: &'static [MemoryRegion],
-    next: usize,
+    physical_memory_offset: VirtAddr,
+    /// Index of the region from which the next frame is allocated
+    region: usize,
+    /// Address of the next frame in the current region
+    next: u64,
+    /// First range of deallocated frames and frames skipped to align contiguous allocations
+    free: Option<PhysFrame>,
+    /// Number of owners of frames which have more than one
+    shared: BTreeMap<PhysFrame, usize>,
+}
+
+/// Header of a range of free frames, stored in its first frame.
+struct FreeRange {
+    next: Option<PhysFrame>,
+    frames: u64,
 }
 
 impl BootInfoFrameAllocator {
@@ -165,31 +744,154 @@ impl BootInfoFrameAllocator {
     ///
     /// Caller of this function  must guarantee that the passed
     /// `memory_regions` are valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    unsafe fn init(memory_regions: &'static MemoryRegions) -> Self {
+    /// as `USABLE` in it are really unused. The complete physical memory must be mapped at
+    /// `physical_memory_offset`.
+    unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
         BootInfoFrameAllocator {
             memory_regions,
+            physical_memory_offset,
+            region: 0,
             next: 0,
+            free: None,
+            shared: BTreeMap::new(),
         }
     }
 
-    /// Returns an iterator over the usable frames specified in [`MemoryRegions`].
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        self.memory_regions
+    /// Allocates physically contiguous frames for a huge page of size `S`.
+    ///
+    /// Frames skipped to align the allocation remain available for smaller allocations.
+    fn allocate_contiguous<S: PageSize>(&mut self) -> Option<PhysFrame<S>> {
+        let (index, aligned) = self
+            .memory_regions
             .iter()
-            .filter(|r| r.kind == MemoryRegionKind::Usable)
-            // map each region to its address range
-            .map(|r| r.start..r.end)
-            // transform to an iterator of frame start addresses
-            .flat_map(|r| r.step_by(PAGE_FRAME_SIZE))
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+            .enumerate()
+            .skip(self.region)
+            .filter(|(_, region)| region.kind == MemoryRegionKind::Usable)
+            .find_map(|(index, region)| {
+                let start = if index == self.region {
+                    self.next.max(region.start)
+                } else {
+                    region.start
+                };
+                let aligned = start.checked_add(S::SIZE - 1)? & !(S::SIZE - 1);
+                (aligned.checked_add(S::SIZE)? <= region.end).then_some((index, aligned))
+            })?;
+
+        // the frames passed over stay available
+        for skipped in self.region..index {
+            let region = &self.memory_regions[skipped];
+            if region.kind == MemoryRegionKind::Usable {
+                let start = if skipped == self.region {
+                    self.next.max(region.start)
+                } else {
+                    region.start
+                };
+                self.free_range(start, region.end);
+            }
+        }
+        let start = if index == self.region {
+            self.next.max(self.memory_regions[index].start)
+        } else {
+            self.memory_regions[index].start
+        };
+        self.free_range(start, aligned);
+
+        self.region = index;
+        self.next = aligned + S::SIZE;
+        Some(PhysFrame::containing_address(PhysAddr::new(aligned)))
+    }
+
+    /// Returns the frames of a huge page from [`BootInfoFrameAllocator::allocate_contiguous`].
+    fn deallocate_contiguous<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        let start = frame.start_address().as_u64();
+        self.free_range(start, start + S::SIZE);
+    }
+
+    /// Adds the frames from `start` to `end` to the free frames.
+    fn free_range(&mut self, start: u64, end: u64) {
+        let frame_size = PAGE_FRAME_SIZE as u64;
+        let (start, end) = (
+            (start + frame_size - 1) & !(frame_size - 1),
+            end & !(frame_size - 1),
+        );
+        if start >= end {
+            return;
+        }
+        let frame = PhysFrame::containing_address(PhysAddr::new(start));
+        // # Safety
+        // the frames are free, so the header can be stored in the first one
+        unsafe {
+            self.free_range_header(frame).write(FreeRange {
+                next: self.free,
+                frames: (end - start) / frame_size,
+            });
+        }
+        self.free = Some(frame);
+    }
+
+    fn free_range_header(&self, frame: PhysFrame) -> *mut FreeRange {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    /// Adds an owner to an allocated frame.
+    fn share(&mut self, frame: PhysFrame) {
+        *self.shared.entry(frame).or_insert(1) += 1;
+    }
+
+    fn is_shared(&self, frame: PhysFrame) -> bool {
+        self.shared.contains_key(&frame)
+    }
+
+    /// Removes an owner from an allocated frame, deallocating it when it was the last one.
+    fn release(&mut self, frame: PhysFrame) {
+        match self.shared.get_mut(&frame) {
+            Some(2) => {
+                self.shared.remove(&frame);
+            }
+            Some(owners) => *owners -= 1,
+            // # Safety
+            // the frame has no other owners
+            None => unsafe { self.deallocate_frame(frame) },
+        }
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        if let Some(first) = self.free {
+            // # Safety
+            // the first frame of a free range holds its header
+            let range = unsafe { &mut *self.free_range_header(first) };
+            // take frames from the end, the header stays in place until the range is empty
+            range.frames -= 1;
+            if range.frames == 0 {
+                self.free = range.next;
+            }
+            return Some(first + range.frames);
+        }
+        // Walk the regions in order instead of iterating over all usable frames on every
+        // allocation, which would make mapping large regions quadratic
+        loop {
+            let region = self.memory_regions.get(self.region)?;
+            let addr = self.next.max(region.start);
+            if region.kind == MemoryRegionKind::Usable
+                && addr + PAGE_FRAME_SIZE as u64 <= region.end
+            {
+                self.next = addr + PAGE_FRAME_SIZE as u64;
+                return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+            }
+            self.region += 1;
+            self.next = 0;
+        }
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let start = frame.start_address().as_u64();
+        self.free_range(start, start + PAGE_FRAME_SIZE as u64);
     }
 }
@@ -0,0 +1,141 @@
+//! Copy-on-write cloning of address spaces.
+//!
+//! Only the memory areas ([`VmAreas`]) belong to an address space, everything else is kernel
+//! memory shared by all of them. Cloning copies the page tables on the paths to the areas' pages
+//! and shares all other page tables. Writable area pages are shared read-only and marked with
+//! [`COPY_ON_WRITE`], the page fault handler copies them on the first write.
+
+use super::area::{Backing, VmAreas};
+use super::{BootInfoFrameAllocator, PAGE_FRAME_SIZE};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// Marks writable pages which are shared until the first write.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// An address space which is not active.
+#[derive(Debug)]
+pub struct AddressSpace {
+    pub(super) level_4_table: PhysFrame,
+    pub(super) areas: VmAreas,
+}
+
+impl AddressSpace {
+    pub fn areas(&self) -> &VmAreas {
+        &self.areas
+    }
+}
+
+/// Walks the page tables of the areas of an address space.
+pub(super) struct TableWalker<'a> {
+    pub(super) physical_memory_offset: VirtAddr,
+    pub(super) areas: &'a VmAreas,
+    pub(super) frame_allocator: &'a mut BootInfoFrameAllocator,
+}
+
+impl TableWalker<'_> {
+    /// Returns a copy of the page table `source` at `level` mapping addresses from `base`.
+    ///
+    /// Writable area pages are turned into copy-on-write pages in both tables.
+    pub(super) fn clone_table(
+        &mut self,
+        source: PhysFrame,
+        level: u8,
+        base: u64,
+    ) -> Result<PhysFrame, MapToError<Size4KiB>> {
+        let frame = self.allocate_table()?;
+        // # Safety
+        // the tables are distinct and only accessed through the physical memory mapping
+        let (source_table, table) = unsafe {
+            (
+                table(self.physical_memory_offset, source),
+                table(self.physical_memory_offset, frame),
+            )
+        };
+
+        for (index, entry) in source_table.iter_mut().enumerate() {
+            let (start, size) = entry_range(level, base, index);
+            if entry.is_unused() || !self.areas.intersects(start, size) {
+                table[index] = entry.clone();
+                continue;
+            }
+            let flags = entry.flags();
+            if level == 1 {
+                let area = self.areas.find(start).unwrap();
+                if !matches!(area.backing, Backing::Device { .. }) {
+                    if flags.contains(PageTableFlags::WRITABLE) {
+                        entry.set_flags((flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE);
+                    }
+                    self.frame_allocator
+                        .share(PhysFrame::containing_address(entry.addr()));
+                }
+                table[index] = entry.clone();
+            } else if flags.contains(PageTableFlags::HUGE_PAGE) {
+                table[index] = entry.clone();
+            } else {
+                let child = self.clone_table(
+                    PhysFrame::containing_address(entry.addr()),
+                    level - 1,
+                    start.as_u64(),
+                )?;
+                table[index].set_frame(child, flags);
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Releases the area pages of the page table `frame` at `level` mapping addresses from
+    /// `base` and the page tables leading to them, including `frame` itself.
+    pub(super) fn free_table(&mut self, frame: PhysFrame, level: u8, base: u64) {
+        // # Safety
+        // the table belongs to an inactive address space and is accessed only here
+        let table = unsafe { table(self.physical_memory_offset, frame) };
+        for (index, entry) in table.iter().enumerate() {
+            let (start, size) = entry_range(level, base, index);
+            if entry.is_unused() || !self.areas.intersects(start, size) {
+                continue;
+            }
+            let frame = PhysFrame::containing_address(entry.addr());
+            if level == 1 {
+                let area = self.areas.find(start).unwrap();
+                if !matches!(area.backing, Backing::Device { .. }) {
+                    self.frame_allocator.release(frame);
+                }
+            } else if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                self.free_table(frame, level - 1, start.as_u64());
+            }
+        }
+        // # Safety
+        // the table is no longer referenced by the address space
+        unsafe { self.frame_allocator.deallocate_frame(frame) };
+    }
+
+    fn allocate_table(&mut self) -> Result<PhysFrame, MapToError<Size4KiB>> {
+        let frame = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // # Safety
+        // the frame has just been allocated
+        unsafe { table(self.physical_memory_offset, frame).zero() };
+        Ok(frame)
+    }
+}
+
+/// # Safety
+/// The complete physical memory must be mapped at `physical_memory_offset` and `frame` must hold
+/// a page table which is not accessed through any other reference.
+unsafe fn table(physical_memory_offset: VirtAddr, frame: PhysFrame) -> &'static mut PageTable {
+    let virt = physical_memory_offset + frame.start_address().as_u64();
+    &mut *virt.as_mut_ptr::<PageTable>()
+}
+
+/// Returns the first address and the size of the memory mapped by an entry.
+fn entry_range(level: u8, base: u64, index: usize) -> (VirtAddr, u64) {
+    let size = (PAGE_FRAME_SIZE as u64) << (9 * (u64::from(level) - 1));
+    // addresses of the upper half of the level 4 table are sign extended
+    (VirtAddr::new_truncate(base + index as u64 * size), size)
+}
@@ -0,0 +1,99 @@
+//! Virtual memory areas of an address space.
+//!
+//! An area reserves a range of virtual memory without mapping it. Pages are mapped on the first
+//! access by the page fault handler, which fills them from the area's [`Backing`].
+
+use alloc::collections::BTreeMap;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Source of the contents of an area's pages.
+#[derive(Debug, Clone, Copy)]
+pub enum Backing {
+    /// Pages are filled with zeros
+    Zero,
+    /// Pages are filled with the file contents starting at `offset`, zeros past its end
+    File { data: &'static [u8], offset: usize },
+    /// Pages are mapped to the physical memory of a device starting at `address`
+    Device { address: PhysAddr },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VmArea {
+    pub start: VirtAddr,
+    pub size: u64,
+    /// Flags of the pages mapped in the area
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl VmArea {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        (self.start..self.end()).contains(&addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaError {
+    /// The area has no pages, is not page aligned or does not fit into the address space
+    InvalidRange,
+    /// The area overlaps an already registered area
+    Overlap,
+    /// No area starts at the given address
+    NotFound,
+}
+
+/// Non-overlapping areas of an address space, ordered by their start address.
+#[derive(Debug, Default, Clone)]
+pub struct VmAreas {
+    areas: BTreeMap<VirtAddr, VmArea>,
+}
+
+impl VmAreas {
+    pub fn insert(&mut self, area: VmArea) -> Result<(), AreaError> {
+        let end = area.start.as_u64().checked_add(area.size);
+        if area.size == 0
+            || !area.start.is_aligned(4096u64)
+            || area.size % 4096 != 0
+            || end.and_then(|end| VirtAddr::try_new(end).ok()).is_none()
+        {
+            return Err(AreaError::InvalidRange);
+        }
+        let previous = self.areas.range(..area.end()).next_back();
+        if previous.is_some_and(|(_, previous)| previous.end() > area.start) {
+            return Err(AreaError::Overlap);
+        }
+        self.areas.insert(area.start, area);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, start: VirtAddr) -> Result<VmArea, AreaError> {
+        self.areas.remove(&start).ok_or(AreaError::NotFound)
+    }
+
+    /// Returns the area containing `addr`.
+    pub fn find(&self, addr: VirtAddr) -> Option<&VmArea> {
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.contains(addr))
+    }
+
+    /// Returns whether any area overlaps the `size` bytes starting at `start`.
+    pub fn intersects(&self, start: VirtAddr, size: u64) -> bool {
+        let end = start.as_u64().saturating_add(size);
+        self.areas
+            .range(..VirtAddr::new_truncate(end))
+            .next_back()
+            .is_some_and(|(_, area)| area.end() > start)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VmArea> {
+        self.areas.values()
+    }
+}
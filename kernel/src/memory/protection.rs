@@ -0,0 +1,141 @@
+//! W^X enforcement for the kernel address space.
+//!
+//! The section headers of the kernel executable are not loaded into memory, but the program
+//! headers are, as the ELF header is part of the first loadable segment. The linker groups the
+//! sections into segments by their permissions (`.text` into an executable one, `.rodata` into a
+//! read-only one and `.data`/`.bss` into writable ones), so the segments are remapped instead.
+
+use super::MemoryManager;
+use bootloader_api::info::MemoryRegions;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::FlagUpdateError;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_RELRO: u32 = 0x6474_E552;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+extern "C" {
+    /// Defined by the linker at the ELF header of the kernel executable.
+    static __ehdr_start: u8;
+}
+
+/// A program header of the kernel executable.
+struct Segment {
+    kind: u32,
+    flags: u32,
+    /// Link address of the segment, the first segment is linked at address 0
+    offset: u64,
+    size: u64,
+}
+
+impl MemoryManager {
+    /// Enables NX and write protection and remaps the kernel image and the physical memory
+    /// mapping so that no memory is both writable and executable.
+    pub(super) fn protect_kernel(
+        &mut self,
+        memory_regions: &MemoryRegions,
+    ) -> Result<(), FlagUpdateError> {
+        // # Safety
+        // the bootloader maps the kernel with NO_EXECUTE set only on non-executable segments
+        unsafe {
+            Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+            Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+        }
+
+        // # Safety
+        // the symbol is defined by the linker
+        let image_start = VirtAddr::from_ptr(unsafe { &__ehdr_start });
+        let segments = kernel_segments(image_start);
+
+        for segment in segments.clone().filter(|segment| segment.kind == PT_LOAD) {
+            let mut flags = PageTableFlags::PRESENT;
+            if segment.flags & PF_W != 0 {
+                flags |= PageTableFlags::WRITABLE;
+            }
+            if segment.flags & PF_X == 0 {
+                flags |= PageTableFlags::NO_EXECUTE;
+            }
+            let start = image_start + segment.offset;
+            self.update_kernel_flags(start, start + segment.size, flags)?;
+        }
+
+        // data which is only written while relocating is read-only afterwards, the linker pads
+        // the segment to the end of a page
+        for segment in segments.filter(|segment| segment.kind == PT_GNU_RELRO) {
+            let start = image_start + segment.offset;
+            let end = (start + segment.size).align_down(4096u64);
+            if start < end {
+                self.update_kernel_flags(
+                    start,
+                    end,
+                    PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+                )?;
+            }
+        }
+
+        // the physical memory mapping occupies level 4 entries of its own
+        let physical_memory_end = memory_regions
+            .iter()
+            .map(|region| region.end)
+            .max()
+            .unwrap_or(0);
+        let offset = self.mapper.phys_offset();
+        let first = Page::<Size4KiB>::containing_address(offset).p4_index();
+        let last = Page::<Size4KiB>::containing_address(offset + physical_memory_end.max(1) - 1u64)
+            .p4_index();
+        let level_4_table = self.mapper.level_4_table();
+        for index in u16::from(first)..=u16::from(last) {
+            let entry = &mut level_4_table[usize::from(index)];
+            entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+        }
+
+        x86_64::instructions::tlb::flush_all();
+        Ok(())
+    }
+
+    /// Sets `flags` on the kernel pages from `start` to `end`.
+    fn update_kernel_flags(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), FlagUpdateError> {
+        let first = Page::<Size4KiB>::containing_address(start);
+        let last = Page::containing_address(end - 1u64);
+        for page in Page::range_inclusive(first, last) {
+            // # Safety
+            // the flags match the permissions the kernel was linked with
+            unsafe { self.mapper.update_flags(page, flags)?.ignore() };
+        }
+        Ok(())
+    }
+}
+
+/// Returns the program headers of the kernel executable loaded at `image_start`.
+fn kernel_segments(image_start: VirtAddr) -> impl Iterator<Item = Segment> + Clone {
+    let read = move |offset: u64, size: usize| -> u64 {
+        let mut bytes = [0; 8];
+        let source = (image_start + offset).as_ptr::<u8>();
+        // # Safety
+        // the ELF and program headers are part of the first loadable segment
+        unsafe { core::ptr::copy_nonoverlapping(source, bytes.as_mut_ptr(), size) };
+        u64::from_le_bytes(bytes)
+    };
+    let header_offset = read(0x20, 8);
+    let header_size = read(0x36, 2);
+    let header_count = read(0x38, 2);
+
+    (0..header_count).map(move |index| {
+        let header = header_offset + index * header_size;
+        Segment {
+            kind: read(header, 4) as u32,
+            flags: read(header + 4, 4) as u32,
+            offset: read(header + 0x10, 8),
+            size: read(header + 0x28, 8),
+        }
+    })
+}
@@ -0,0 +1,128 @@
+//! Kernel stacks with guard pages.
+//!
+//! Every stack is mapped below an unmapped guard page, so overflowing it faults instead of
+//! silently overwriting the memory below. The stacks are registered with a name, which lets the
+//! fault handlers tell which stack overflowed.
+
+use super::MEMORY_MANAGER;
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// Start of the virtual memory region from which stacks are allocated.
+const STACKS_START: u64 = 0x5555_0000_0000;
+
+/// Maximum number of pages searched for the guard page of the current stack.
+const MAX_STACK_PAGES: u64 = 1024;
+
+static STACKS: SpinLock<StackRegistry> = SpinLock::new(StackRegistry {
+    next: STACKS_START,
+    stacks: Vec::new(),
+});
+
+struct StackRegistry {
+    /// Start of the next allocated stack's guard page
+    next: u64,
+    stacks: Vec<KernelStack>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    name: &'static str,
+    guard_page: Page,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    /// Name of the thread or interrupt stack table slot using the stack.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Exclusive end of the stack, the initial stack pointer.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// The unmapped page directly below the stack.
+    pub fn guard_page(&self) -> Page {
+        self.guard_page
+    }
+}
+
+/// Allocates a stack of `size` bytes, rounded up to whole pages, below an unmapped guard page.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized.
+pub fn allocate(name: &'static str, size: usize) -> Result<KernelStack, MapToError<Size4KiB>> {
+    let size = (size as u64 + 4095) & !4095;
+    let mut registry = STACKS.lock();
+    let guard_page = Page::containing_address(VirtAddr::new(registry.next));
+    let bottom = guard_page.start_address() + 4096u64;
+
+    MEMORY_MANAGER
+        .get()
+        .unwrap()
+        .lock()
+        .allocate_frames_for_memory_region(
+            bottom,
+            size as usize,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )?;
+
+    let stack = KernelStack {
+        name,
+        guard_page,
+        top: bottom + size,
+    };
+    registry.next = stack.top.as_u64();
+    registry.stacks.push(stack);
+    Ok(stack)
+}
+
+/// Registers the stack in use under `name`, e.g. the boot stack set up by the bootloader.
+///
+/// The guard page is the first unmapped page below the stack pointer. Returns [`None`] if there
+/// is no such page close to the stack pointer.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized.
+pub fn register_current(name: &'static str) -> Option<KernelStack> {
+    let stack_pointer: u64;
+    // # Safety
+    // reading rsp has no side effects
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack));
+    }
+    let top = Page::<Size4KiB>::containing_address(VirtAddr::new(stack_pointer)) + 1;
+
+    let manager = MEMORY_MANAGER.get().unwrap().lock();
+    let guard_page = (1..=MAX_STACK_PAGES)
+        .map(|pages| top - pages)
+        .find(|page| manager.translate_address(page.start_address()).is_none())?;
+    drop(manager);
+
+    let stack = KernelStack {
+        name,
+        guard_page,
+        top: top.start_address(),
+    };
+    STACKS.lock().stacks.push(stack);
+    Some(stack)
+}
+
+/// Returns the stack whose guard page contains `addr`.
+///
+/// Returns [`None`] as well if the stacks are being registered, so it is safe to call from
+/// exception handlers.
+pub fn overflowed_stack(addr: VirtAddr) -> Option<KernelStack> {
+    let page = Page::containing_address(addr);
+    STACKS
+        .try_lock()?
+        .stacks
+        .iter()
+        .find(|stack| stack.guard_page == page)
+        .copied()
+}
@@ -0,0 +1,144 @@
+//! TLB shootdown.
+//!
+//! Every processor caches translations in its own TLB, so changing or removing a mapping of the
+//! shared kernel address space must invalidate it on all processors. The processor changing the
+//! mapping collects the pages in a [`Shootdown`], flushes them locally and sends the batch to
+//! the other online processors with a single inter-processor interrupt each. It waits until all
+//! of them have acknowledged the flush, so the old translations are gone when it returns.
+//!
+//! The memory manager collects the pages it unmaps while it is locked and finishes the batch
+//! after releasing the lock, see [`super::MemoryManagerLock`].
+//!
+//! Which address space each processor has loaded is not tracked, so every shootdown is sent to
+//! all online processors, also for pages of user address spaces.
+
+use crate::interrupt::{apic, InterruptIndex};
+use crate::percpu;
+use crate::smp;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, RwLock};
+use x86_64::instructions::tlb;
+use x86_64::structures::paging::{Page, Size4KiB};
+use x86_64::VirtAddr;
+
+/// Maximum number of pages in a batch, larger batches flush the complete TLB.
+pub const MAX_BATCH: usize = 32;
+
+/// Serializes shootdowns, as there is only a single request.
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+static REQUEST: RwLock<Shootdown> = RwLock::new(Shootdown::new());
+/// Processors which have not yet flushed the current request
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// A batch of pages whose translations are invalidated on all processors.
+#[derive(Debug, Clone, Copy)]
+pub struct Shootdown {
+    pages: [VirtAddr; MAX_BATCH],
+    len: usize,
+    flush_all: bool,
+}
+
+impl Default for Shootdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shootdown {
+    pub const fn new() -> Self {
+        Self {
+            pages: [VirtAddr::zero(); MAX_BATCH],
+            len: 0,
+            flush_all: false,
+        }
+    }
+
+    /// Adds `page` to the batch.
+    pub fn add(&mut self, page: Page<Size4KiB>) {
+        match self.pages.get_mut(self.len) {
+            Some(slot) => {
+                *slot = page.start_address();
+                self.len += 1;
+            }
+            None => self.flush_all = true,
+        }
+    }
+
+    /// Invalidates the complete TLB instead of single pages.
+    pub fn add_all(&mut self) {
+        self.flush_all = true;
+    }
+
+    /// Invalidates the pages on all online processors and waits until they are done.
+    pub fn finish(self) {
+        if self.len == 0 && !self.flush_all {
+            return;
+        }
+        self.flush_local();
+
+        let current = percpu::cpu_id();
+        let targets = smp::cpus()
+            .iter()
+            .filter(|cpu| cpu.index() != current && cpu.is_online())
+            .fold(0u64, |mask, cpu| mask | 1 << cpu.index());
+        if targets == 0 {
+            return;
+        }
+
+        // another processor may wait for this one to handle its requests in the meantime, even
+        // with interrupts disabled
+        let _shootdown = loop {
+            if let Some(guard) = SHOOTDOWN.try_lock() {
+                break guard;
+            }
+            smp::handle_pending_requests();
+            core::hint::spin_loop();
+        };
+        *REQUEST.write() = self;
+        PENDING.store(targets, Ordering::Release);
+        for cpu in smp::cpus() {
+            if targets & 1 << cpu.index() != 0 {
+                apic::send_ipi(cpu.apic_id(), InterruptIndex::TlbShootdown.into());
+            }
+        }
+        while PENDING.load(Ordering::Acquire) != 0 {
+            smp::handle_pending_requests();
+            core::hint::spin_loop();
+        }
+    }
+
+    fn flush_local(&self) {
+        if self.flush_all {
+            tlb::flush_all();
+        } else {
+            for page in &self.pages[..self.len] {
+                tlb::flush(*page);
+            }
+        }
+    }
+}
+
+/// Invalidates `page` on all processors.
+pub fn shootdown(page: Page<Size4KiB>) {
+    let mut shootdown = Shootdown::new();
+    shootdown.add(page);
+    shootdown.finish();
+}
+
+/// Invalidates the complete TLB of all processors.
+pub fn shootdown_all() {
+    let mut shootdown = Shootdown::new();
+    shootdown.add_all();
+    shootdown.finish();
+}
+
+/// Flushes the current request if the current processor has not done so yet, called by the
+/// shootdown interrupt handler.
+pub(crate) fn flush_pending() {
+    let cpu = 1u64 << percpu::cpu_id();
+    if PENDING.load(Ordering::Acquire) & cpu == 0 {
+        return;
+    }
+    REQUEST.read().flush_local();
+    PENDING.fetch_and(!cpu, Ordering::AcqRel);
+}
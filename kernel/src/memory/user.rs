@@ -0,0 +1,263 @@
+//! Access to user memory from the kernel.
+//!
+//! With SMEP the kernel cannot execute user pages and with SMAP it cannot access them, except
+//! between `stac` and `clac`. The helpers of this module check that a range belongs to the user
+//! accessible areas of the active address space and copy it with access enabled. The copy loop
+//! is listed in an exception table, a page fault inside it that cannot be resolved makes the
+//! helper return [`Efault`] instead of panicking.
+
+use super::MEMORY_MANAGER;
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// End of the lower half of the address space, user memory lies below it.
+const USER_END: u64 = 0x0000_8000_0000_0000;
+
+/// Whether SMAP is enabled, `stac` and `clac` are invalid instructions otherwise.
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    /// The instruction of [`copy_bytes`] which accesses user memory.
+    static __user_copy_start: u8;
+    /// The instruction after it, which returns the number of bytes not copied.
+    static __user_copy_fixup: u8;
+}
+
+/// The user memory range is not accessible (`EFAULT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Efault;
+
+impl fmt::Display for Efault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("bad address")
+    }
+}
+
+/// Types which are valid for any bit pattern and can therefore be read from user memory.
+///
+/// # Safety
+/// The type must not have invalid bit patterns or padding.
+pub unsafe trait Pod: Copy {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// A pointer to a `T` in user memory, which is only accessed through the checked helpers.
+pub struct UserPtr<T> {
+    addr: VirtAddr,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> fmt::Debug for UserPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UserPtr({:#x})", self.addr.as_u64())
+    }
+}
+
+impl<T> UserPtr<T> {
+    pub fn new(addr: VirtAddr) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn addr(self) -> VirtAddr {
+        self.addr
+    }
+
+    /// Returns the pointer to the `count`th `T` after this one.
+    ///
+    /// Overflowing pointers are not checked until they are accessed.
+    pub fn offset(self, count: usize) -> Self {
+        let offset = (count as u64).wrapping_mul(size_of::<T>() as u64);
+        Self::new(VirtAddr::new_truncate(
+            self.addr.as_u64().wrapping_add(offset),
+        ))
+    }
+}
+
+impl<T: Pod> UserPtr<T> {
+    pub fn read(self) -> Result<T, Efault> {
+        let mut value = MaybeUninit::<T>::uninit();
+        // # Safety
+        // the bytes are only read after all of them have been written
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr().cast::<u8>(), size_of::<T>())
+        };
+        copy_from_user(bytes, UserPtr::new(self.addr))?;
+        // # Safety
+        // the value has been copied completely and any bit pattern is valid for `T`
+        Ok(unsafe { value.assume_init() })
+    }
+
+    pub fn write(self, value: T) -> Result<(), Efault> {
+        // # Safety
+        // `T` has no padding, so all of its bytes are initialized
+        let bytes = unsafe {
+            core::slice::from_raw_parts((&value as *const T).cast::<u8>(), size_of::<T>())
+        };
+        copy_to_user(UserPtr::new(self.addr), bytes)
+    }
+}
+
+/// Enables SMEP, SMAP and UMIP on the current processor if it supports them.
+pub(crate) fn enable_protection() {
+    const STRUCTURED_EXTENDED_FEATURES: u32 = 7;
+    const SMEP: u32 = 1 << 7;
+    const SMAP: u32 = 1 << 20;
+    const UMIP: u32 = 1 << 2;
+
+    // # Safety
+    // CPUID is available on every x86_64 CPU
+    let (max_leaf, features) = unsafe {
+        let max_leaf = __cpuid_count(0, 0).eax;
+        (max_leaf, __cpuid_count(STRUCTURED_EXTENDED_FEATURES, 0))
+    };
+    if max_leaf < STRUCTURED_EXTENDED_FEATURES {
+        return;
+    }
+
+    let mut flags = Cr4Flags::empty();
+    if features.ebx & SMEP != 0 {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if features.ebx & SMAP != 0 {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    if features.ecx & UMIP != 0 {
+        flags |= Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION;
+    }
+
+    // # Safety
+    // the kernel is not mapped user accessible and accesses user memory only through this module
+    unsafe { Cr4::update(|cr4| cr4.insert(flags)) };
+    SMAP_ENABLED.store(
+        flags.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        Ordering::Relaxed,
+    );
+}
+
+/// Copies `dst.len()` bytes from user memory at `src` into `dst`.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized and deadlocks if it is
+/// locked by the caller.
+pub fn copy_from_user(dst: &mut [u8], src: UserPtr<u8>) -> Result<(), Efault> {
+    check_range(src.addr, dst.len(), PageTableFlags::empty())?;
+    // # Safety
+    // the source is user memory, which the kernel never references otherwise
+    unsafe { copy(dst.as_mut_ptr(), src.addr.as_ptr(), dst.len()) }
+}
+
+/// Copies `src` into user memory at `dst`.
+///
+/// # Panics
+/// The function will panic if the memory manager is not initialized and deadlocks if it is
+/// locked by the caller.
+pub fn copy_to_user(dst: UserPtr<u8>, src: &[u8]) -> Result<(), Efault> {
+    check_range(dst.addr, src.len(), PageTableFlags::WRITABLE)?;
+    // # Safety
+    // the destination is user memory, which the kernel never references otherwise
+    unsafe { copy(dst.addr.as_mut_ptr(), src.as_ptr(), src.len()) }
+}
+
+/// Returns the address to resume at if an unresolved page fault at `rip` was caused by the copy
+/// routine.
+pub fn exception_fixup(rip: u64) -> Option<u64> {
+    // # Safety
+    // the symbols are defined by `copy_bytes`
+    let (start, fixup) = unsafe {
+        (
+            &__user_copy_start as *const u8 as u64,
+            &__user_copy_fixup as *const u8 as u64,
+        )
+    };
+    (rip == start).then_some(fixup)
+}
+
+/// Checks that the `len` bytes at `addr` belong to user accessible areas with `access`.
+fn check_range(addr: VirtAddr, len: usize, access: PageTableFlags) -> Result<(), Efault> {
+    let end = addr.as_u64().checked_add(len as u64).ok_or(Efault)?;
+    if len == 0 {
+        return Ok(());
+    }
+    if end > USER_END {
+        return Err(Efault);
+    }
+
+    let manager = MEMORY_MANAGER.get().unwrap().lock();
+    let mut next = addr;
+    while next.as_u64() < end {
+        let area = manager.areas().find(next).ok_or(Efault)?;
+        if !area
+            .flags
+            .contains(PageTableFlags::USER_ACCESSIBLE | access)
+        {
+            return Err(Efault);
+        }
+        next = area.end();
+    }
+    Ok(())
+}
+
+/// # Safety
+/// One of the ranges must be kernel memory valid for the access and the other one user memory.
+unsafe fn copy(dst: *mut u8, src: *const u8, len: usize) -> Result<(), Efault> {
+    let smap = SMAP_ENABLED.load(Ordering::Relaxed);
+    if smap {
+        asm!("stac", options(nostack));
+    }
+    let remaining = copy_bytes(dst, src, len);
+    if smap {
+        asm!("clac", options(nostack));
+    }
+    match remaining {
+        0 => Ok(()),
+        _ => Err(Efault),
+    }
+}
+
+/// Copies `len` bytes and returns the number of bytes which were not copied because of a fault.
+///
+/// The labels are global symbols so that [`exception_fixup`] can find them, the function is not
+/// generic and never inlined, so they are defined exactly once.
+#[naked]
+#[allow(named_asm_labels)]
+unsafe extern "C" fn copy_bytes(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    asm!(
+        "mov rcx, rdx",
+        ".globl __user_copy_start",
+        ".hidden __user_copy_start",
+        "__user_copy_start:",
+        "rep movsb",
+        ".globl __user_copy_fixup",
+        ".hidden __user_copy_fixup",
+        "__user_copy_fixup:",
+        "mov rax, rcx",
+        "ret",
+        options(noreturn)
+    )
+}
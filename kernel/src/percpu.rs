@@ -0,0 +1,266 @@
+//! Per-CPU data.
+//!
+//! The GS base of every processor points at its [`CpuArea`], so the current processor's data is
+//! reached with a single `gs`-relative access that cannot be torn by an interrupt or a move to
+//! another processor. `IA32_KERNEL_GS_BASE` holds the GS base of user mode. Every interrupt and
+//! exception entry exchanges both with `swapgs` when entering from and returning to user mode:
+//! the exception trampolines do so themselves, the other handlers create a [`KernelGsGuard`]
+//! before anything else.
+//!
+//! [`PerCpu`] holds one value per processor, which is created on the first access from that
+//! processor. The value of the current processor must only be accessed while preemption is
+//! disabled with [`preempt_disable`], as the task could otherwise continue on another processor
+//! while it still holds a reference.
+
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::sync::atomic::AtomicUsize;
+use spin::Once;
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+/// Maximum number of processors, the others are not started.
+pub const MAX_CPUS: usize = 64;
+
+static AREAS: [CpuArea; MAX_CPUS] = {
+    let mut areas = [CpuArea::EMPTY; MAX_CPUS];
+    let mut cpu = 0;
+    while cpu < MAX_CPUS {
+        areas[cpu].cpu_id = cpu;
+        cpu += 1;
+    }
+    areas
+};
+
+/// Data of a processor accessed through its GS base, the offsets of the fields are used by the
+/// accessors below.
+#[repr(C)]
+struct CpuArea {
+    /// At offset 0
+    cpu_id: usize,
+    /// At offset 8, number of active [`PreemptGuard`]s, only changed by the processor itself
+    preempt_count: AtomicUsize,
+    /// At offset 16, number of nested interrupt handlers running, see [`enter_interrupt`]
+    interrupt_depth: AtomicUsize,
+}
+
+impl CpuArea {
+    // only used to initialize the array of areas
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: CpuArea = CpuArea {
+        cpu_id: 0,
+        preempt_count: AtomicUsize::new(0),
+        interrupt_depth: AtomicUsize::new(0),
+    };
+}
+
+/// Points the GS base of the current processor at the area of `cpu`.
+///
+/// # Panics
+/// The function will panic if `cpu` is not below [`MAX_CPUS`].
+pub fn init(cpu: usize) {
+    let area = &AREAS[cpu];
+    GsBase::write(VirtAddr::from_ptr(area));
+    KernelGsBase::write(VirtAddr::zero());
+}
+
+/// Returns the index of the current processor, the bootstrap processor has index 0.
+pub fn cpu_id() -> usize {
+    let cpu_id: usize;
+    // # Safety
+    // the GS base points at the area of the current processor
+    unsafe {
+        asm!(
+            "mov {}, gs:[0]",
+            out(reg) cpu_id,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    cpu_id
+}
+
+/// Switches to the kernel's GS base in an interrupt handler and back to the interrupted one when
+/// dropped.
+///
+/// It must be created before anything in the handler accesses the per-CPU data.
+#[derive(Debug)]
+pub struct KernelGsGuard {
+    swapped: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl KernelGsGuard {
+    /// Swaps the GS base if the handler interrupted user mode.
+    pub fn enter(frame: &InterruptStackFrame) -> Self {
+        Self::swap_if(frame.code_segment & 3 != 0)
+    }
+
+    /// Swaps the GS base unless it already points at the area of a processor.
+    ///
+    /// For NMIs, machine checks and double faults, which may also interrupt the kernel right
+    /// after entering from user mode, before it has swapped the GS base.
+    pub fn enter_paranoid() -> Self {
+        let areas = AREAS.as_ptr_range();
+        let gs_base = GsBase::read().as_ptr::<CpuArea>();
+        Self::swap_if(!areas.contains(&gs_base))
+    }
+
+    fn swap_if(swap: bool) -> Self {
+        if swap {
+            // # Safety
+            // the other GS base belongs to the kernel, it is swapped back on drop
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+        Self {
+            swapped: swap,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for KernelGsGuard {
+    fn drop(&mut self) {
+        if self.swapped {
+            // # Safety
+            // restores the GS base of the interrupted code
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
+
+/// Disables preemption of the current task until the guard is dropped.
+///
+/// Guards may be nested, preemption is enabled again when the last one is dropped.
+pub fn preempt_disable() -> PreemptGuard {
+    // # Safety
+    // a single instruction, so an interrupt cannot observe a partial update
+    unsafe {
+        asm!("add qword ptr gs:[8], 1", options(nostack));
+    }
+    PreemptGuard {
+        _not_send: PhantomData,
+    }
+}
+
+/// Returns whether the current task may be moved to another processor.
+pub fn preemptible() -> bool {
+    let count: usize;
+    // # Safety
+    // the GS base points at the area of the current processor
+    unsafe {
+        asm!(
+            "mov {}, gs:[8]",
+            out(reg) count,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    count == 0 && x86_64::instructions::interrupts::are_enabled()
+}
+
+/// Keeps preemption disabled while it exists, see [`preempt_disable`].
+///
+/// The guard belongs to the processor it was created on and cannot be sent to another one.
+#[derive(Debug)]
+pub struct PreemptGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        // # Safety
+        // see `preempt_disable`
+        unsafe {
+            asm!("sub qword ptr gs:[8], 1", options(nostack));
+        }
+    }
+}
+
+/// Marks the current processor as running an interrupt handler until the guard is dropped.
+///
+/// Hardware interrupt handlers call this first, exception handlers do not.
+pub fn enter_interrupt() -> InterruptGuard {
+    // # Safety
+    // see `preempt_disable`
+    unsafe {
+        asm!("add qword ptr gs:[16], 1", options(nostack));
+    }
+    InterruptGuard {
+        _not_send: PhantomData,
+    }
+}
+
+/// Returns whether the current processor is running an interrupt handler.
+pub fn in_interrupt() -> bool {
+    let depth: usize;
+    // # Safety
+    // the GS base points at the area of the current processor
+    unsafe {
+        asm!(
+            "mov {}, gs:[16]",
+            out(reg) depth,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    depth > 0
+}
+
+/// Leaves the interrupt context when dropped, see [`enter_interrupt`].
+#[derive(Debug)]
+pub struct InterruptGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // # Safety
+        // see `preempt_disable`
+        unsafe {
+            asm!("sub qword ptr gs:[16], 1", options(nostack));
+        }
+    }
+}
+
+/// A value for every processor.
+pub struct PerCpu<T> {
+    values: [Once<T>; MAX_CPUS],
+    init: fn() -> T,
+}
+
+// # Safety
+// the value of a processor is only accessed by that processor, unless it is `Sync`
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    // only used to initialize the array of values
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNINITIALIZED: Once<T> = Once::new();
+
+    /// Creates the values lazily with `init`.
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            values: [Self::UNINITIALIZED; MAX_CPUS],
+            init,
+        }
+    }
+
+    /// Returns the value of the current processor.
+    pub fn get<'a>(&'a self, _guard: &'a PreemptGuard) -> &'a T {
+        self.values[cpu_id()].call_once(self.init)
+    }
+}
+
+impl<T: Sync> PerCpu<T> {
+    /// Returns the value of `cpu` if it has been created.
+    pub fn get_for(&self, cpu: usize) -> Option<&T> {
+        self.values.get(cpu)?.get()
+    }
+
+    /// Returns the created values and the indices of their processors.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(cpu, value)| Some((cpu, value.get()?)))
+    }
+}
@@ -0,0 +1,270 @@
+//! Start-up of the application processors.
+//!
+//! The bootstrap processor runs [`crate::init`] and then starts the application processors
+//! listed in the MADT one after another with the INIT-SIPI-SIPI sequence. Each of them enters
+//! the kernel through the real mode [`trampoline`], sets up its own descriptor tables,
+//! interrupt stacks and local APIC timer and checks in before the next one is started.
+//!
+//! A processor which does not acknowledge its startup parameters in time is stopped with another
+//! INIT, so it cannot pick up the parameters of the next processor or run the trampoline after
+//! it has been removed.
+//!
+//! A panicking processor stops the others with an NMI, see [`stop_other_cpus`].
+
+mod call;
+mod trampoline;
+
+pub(crate) use call::run_pending_calls;
+pub use call::{call_on_cpu, CallError};
+
+use crate::acpi::{self, AcpiError};
+use crate::interrupt::{self, apic};
+use crate::memory::{self, stack, tlb, MEMORY_MANAGER};
+use crate::percpu::{self, MAX_CPUS};
+use alloc::format;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Once;
+use trampoline::{Parameters, Trampoline};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::Size4KiB;
+use x86_64::PhysAddr;
+
+const AP_STACK_SIZE: usize = 64 * 1024;
+
+/// Time an application processor gets to acknowledge its parameters after its startup
+/// interrupts, and again to check in afterwards.
+const STARTUP_TIMEOUT_MICROSECONDS: u64 = 100_000;
+
+/// Marks that no processor is stopping the others
+const NO_CPU: usize = usize::MAX;
+
+static CPUS: Once<Vec<Cpu>> = Once::new();
+/// Processor stopping the others, [`NO_CPU`] until one panics
+static STOPPING_CPU: AtomicUsize = AtomicUsize::new(NO_CPU);
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub enum SmpError {
+    /// The bootloader did not find the ACPI tables
+    NoRsdp,
+    Acpi(AcpiError),
+    /// There is no free memory below 1 MiB for the trampoline
+    NoRealModeMemory,
+    /// The page tables are above 4 GiB, where the trampoline cannot load them from
+    PageTableTooHigh,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<AcpiError> for SmpError {
+    fn from(error: AcpiError) -> Self {
+        SmpError::Acpi(error)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        SmpError::Map(error)
+    }
+}
+
+/// A processor listed in the MADT.
+#[derive(Debug)]
+pub struct Cpu {
+    index: usize,
+    apic_id: u32,
+    online: AtomicBool,
+}
+
+impl Cpu {
+    /// Index of the processor, the bootstrap processor has index 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id
+    }
+
+    /// Whether the processor has checked in after starting.
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+}
+
+/// Starts all enabled application processors and returns the number of online processors.
+///
+/// Processors which do not check in in time are left offline, as are processors beyond
+/// [`MAX_CPUS`]. Processors which do not even acknowledge their parameters are stopped.
+///
+/// # Panics
+/// The function will panic if it is called more than once or before [`crate::init`].
+pub fn init(rsdp: Option<PhysAddr>) -> Result<usize, SmpError> {
+    assert!(
+        CPUS.get().is_none(),
+        "application processors already started"
+    );
+    let madt = acpi::init(rsdp.ok_or(SmpError::NoRsdp)?)?;
+
+    let bsp_apic_id = apic::id();
+    let mut cpus = Vec::from([Cpu {
+        index: 0,
+        apic_id: bsp_apic_id,
+        online: AtomicBool::new(true),
+    }]);
+    cpus.extend(
+        madt.processors
+            .iter()
+            .filter(|processor| processor.enabled && processor.apic_id != bsp_apic_id)
+            .take(MAX_CPUS - 1)
+            .enumerate()
+            .map(|(index, processor)| Cpu {
+                index: index + 1,
+                apic_id: processor.apic_id,
+                online: AtomicBool::new(false),
+            }),
+    );
+    let cpus = CPUS.call_once(|| cpus);
+    if cpus.len() == 1 {
+        return Ok(1);
+    }
+
+    let page_table = Cr3::read().0.start_address().as_u64();
+    if page_table > u64::from(u32::MAX) {
+        return Err(SmpError::PageTableTooHigh);
+    }
+    let frame = MEMORY_MANAGER
+        .get()
+        .unwrap()
+        .lock()
+        .real_mode_frame()
+        .ok_or(SmpError::NoRealModeMemory)?;
+    let mut trampoline = Trampoline::install(frame)?;
+
+    let result: Result<(), SmpError> = cpus[1..].iter().try_for_each(|cpu| {
+        let name = format!("main (CPU {})", cpu.index).leak();
+        let stack = stack::allocate(name, AP_STACK_SIZE)?;
+        trampoline.set_parameters(Parameters::new(
+            page_table,
+            stack.top(),
+            ap_entry as usize as u64,
+            cpu.index as u64,
+        ));
+        start(cpu, &trampoline);
+        Ok(())
+    });
+    // every processor has left the trampoline or has been stopped
+    trampoline.remove();
+    result?;
+
+    Ok(cpus.iter().filter(|cpu| cpu.is_online()).count())
+}
+
+/// Returns the processors found by [`init`].
+pub fn cpus() -> &'static [Cpu] {
+    CPUS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Stops all other online processors for good, used when the kernel panics so that the current
+/// processor may take the locks they held.
+///
+/// The processors are sent an NMI, in which they halt with interrupts disabled. Returns whether
+/// all of them stopped within [`STARTUP_TIMEOUT_MICROSECONDS`]. Only the first caller stops the
+/// others, later callers are stopped themselves and `false` is returned to them.
+pub fn stop_other_cpus() -> bool {
+    let current = percpu::cpu_id();
+    if STOPPING_CPU
+        .compare_exchange(NO_CPU, current, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return false;
+    }
+    let others = || {
+        cpus()
+            .iter()
+            .filter(move |cpu| cpu.index != current && cpu.is_online())
+    };
+    for cpu in others() {
+        apic::send_nmi(cpu.apic_id);
+    }
+    let count = others().count();
+    wait_until(|| STOPPED.load(Ordering::Acquire) == count)
+}
+
+/// Halts the current processor if another one is stopping all processors, called by the NMI
+/// handler.
+pub(crate) fn stop_if_requested() {
+    let stopping = STOPPING_CPU.load(Ordering::Acquire);
+    if stopping == NO_CPU || stopping == percpu::cpu_id() {
+        return;
+    }
+    STOPPED.fetch_add(1, Ordering::Release);
+    // interrupts are disabled in the handler and further NMIs are blocked until it returns
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Handles the requests other processors sent the current processor with inter-processor
+/// interrupts, i.e. runs the queued calls and flushes the pending TLB shootdown.
+///
+/// Processors waiting for another one call this while they wait, so two processors waiting for
+/// each other do not deadlock, even with interrupts disabled.
+pub(crate) fn handle_pending_requests() {
+    call::run_pending_calls();
+    tlb::flush_pending();
+}
+
+/// Sends the INIT-SIPI-SIPI sequence to `cpu` and waits for it to check in.
+///
+/// The processor is stopped again if it does not acknowledge the parameters of `trampoline`.
+fn start(cpu: &Cpu, trampoline: &Trampoline) {
+    apic::send_init(cpu.apic_id);
+    apic::wait_microseconds(10_000);
+    for _ in 0..2 {
+        apic::send_startup(cpu.apic_id, trampoline.vector());
+        apic::wait_microseconds(200);
+        if trampoline.is_acknowledged() {
+            break;
+        }
+    }
+
+    if !wait_until(|| trampoline.is_acknowledged()) {
+        // it waits for a startup interrupt again, which is never sent
+        apic::send_init(cpu.apic_id);
+        apic::wait_microseconds(10_000);
+        return;
+    }
+    wait_until(|| cpu.is_online());
+}
+
+/// Waits up to [`STARTUP_TIMEOUT_MICROSECONDS`] for `condition`, returns whether it holds.
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..STARTUP_TIMEOUT_MICROSECONDS / 100 {
+        if condition() {
+            return true;
+        }
+        apic::wait_microseconds(100);
+    }
+    condition()
+}
+
+/// Entry point of the application processors, called by the trampoline on their own stacks.
+extern "C" fn ap_entry(index: u64, acknowledgement: u64) -> ! {
+    // # Safety
+    // the trampoline passes the address of its flag
+    let acknowledged: &AtomicU64 = unsafe { trampoline::acknowledgement(acknowledgement) };
+    // the trampoline and the parameters are not used anymore, nothing else may happen before
+    // as the bootstrap processor stops processors which do not acknowledge in time
+    acknowledged.store(1, Ordering::Release);
+
+    let cpu = &cpus()[index as usize];
+    percpu::init(cpu.index);
+    memory::user::enable_protection();
+    interrupt::init_application_processor(cpu.index);
+    cpu.online.store(true, Ordering::Release);
+
+    interrupt::enable_interrupts();
+    crate::halt_loop()
+}
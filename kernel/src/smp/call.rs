@@ -0,0 +1,88 @@
+//! Running functions on other processors.
+//!
+//! Calls are queued for the target processor, which runs them in the handler of the call
+//! function inter-processor interrupt. The caller waits until its call has run, so the function
+//! may borrow from the caller's stack.
+
+use super::cpus;
+use crate::interrupt::{apic, InterruptIndex};
+use crate::percpu::{self, MAX_CPUS};
+use crate::sync::IrqSpinLock;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// only used to initialize the array of queues
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: IrqSpinLock<VecDeque<Call>> = IrqSpinLock::new(VecDeque::new());
+
+/// The handler locks the queue of its processor, which may have been interrupted while queueing
+/// or running calls itself
+static QUEUES: [IrqSpinLock<VecDeque<Call>>; MAX_CPUS] = [EMPTY_QUEUE; MAX_CPUS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallError {
+    /// The processor does not exist or has not checked in
+    Offline,
+}
+
+struct Call {
+    function: Box<dyn FnOnce() + Send>,
+    done: &'static AtomicBool,
+}
+
+/// Runs `function` on the processor `cpu` and returns its result.
+///
+/// The function runs in interrupt context on the target processor. The requests of other
+/// processors are handled while waiting, so processors calling each other or shooting down
+/// translations do not deadlock, even with interrupts disabled.
+pub fn call_on_cpu<R: Send>(
+    cpu: usize,
+    function: impl FnOnce() -> R + Send,
+) -> Result<R, CallError> {
+    if cpu == percpu::cpu_id() {
+        return Ok(function());
+    }
+    let target = cpus()
+        .get(cpu)
+        .filter(|target| target.is_online())
+        .ok_or(CallError::Offline)?;
+
+    let mut result = None;
+    let done = AtomicBool::new(false);
+    let call: Box<dyn FnOnce() + Send + '_> = Box::new(|| result = Some(function()));
+    // # Safety
+    // the call and the completion flag outlive their use, as this function does not return
+    // before the call has run and the flag has been set
+    let call = unsafe {
+        Call {
+            function: core::mem::transmute::<
+                Box<dyn FnOnce() + Send + '_>,
+                Box<dyn FnOnce() + Send + 'static>,
+            >(call),
+            done: &*(&done as *const AtomicBool),
+        }
+    };
+    QUEUES[cpu].lock().push_back(call);
+    apic::send_ipi(target.apic_id(), InterruptIndex::CallFunction.into());
+
+    while !done.load(Ordering::Acquire) {
+        super::handle_pending_requests();
+        core::hint::spin_loop();
+    }
+    Ok(result.expect("call did not run"))
+}
+
+/// Runs the calls queued for the current processor, called by the call function interrupt
+/// handler.
+pub(crate) fn run_pending_calls() {
+    let queue = &QUEUES[percpu::cpu_id()];
+    loop {
+        // the queue is unlocked while the call runs
+        let Some(call) = queue.lock().pop_front() else {
+            break;
+        };
+        (call.function)();
+        call.done.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,220 @@
+//! Real mode startup code of the application processors.
+//!
+//! A startup interrupt starts a processor in real mode at the beginning of a page below 1 MiB.
+//! The trampoline is copied there and switches straight to long mode with the kernel's page
+//! tables, so it must be identity mapped while it runs. It ends with a block of [`Parameters`]
+//! which the bootstrap processor fills in before starting each processor. The entry function
+//! acknowledges that the processor has read them and left the trampoline, only then may the
+//! parameters be changed or the trampoline be removed.
+
+use crate::memory::MEMORY_MANAGER;
+use core::arch::global_asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+extern "C" {
+    static __ap_trampoline_start: u8;
+    static __ap_trampoline_end: u8;
+}
+
+/// Passed to a starting processor at the end of the trampoline.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Parameters {
+    /// Physical address of the level 4 page table, it must be below 4 GiB
+    pub(super) page_table: u64,
+    pub(super) stack_top: u64,
+    /// Function called with `argument` and `acknowledgement` in long mode, it must not return
+    pub(super) entry: u64,
+    pub(super) argument: u64,
+    /// Virtual address of `acknowledged`, the entry function sets it to a nonzero value
+    acknowledgement: u64,
+    acknowledged: u64,
+}
+
+/// The trampoline installed in a frame below 1 MiB.
+pub(super) struct Trampoline {
+    frame: PhysFrame,
+    parameters: *mut Parameters,
+}
+
+impl Trampoline {
+    /// Copies the trampoline into `frame` and identity maps it.
+    ///
+    /// # Panics
+    /// The function will panic if the memory manager is not initialized.
+    pub(super) fn install(frame: PhysFrame) -> Result<Self, MapToError<Size4KiB>> {
+        // # Safety
+        // the symbols are defined by the assembly below
+        let code = unsafe {
+            let start = &__ap_trampoline_start as *const u8;
+            let end = &__ap_trampoline_end as *const u8;
+            core::slice::from_raw_parts(start, end as usize - start as usize)
+        };
+        assert!(
+            code.len() <= 4096,
+            "the trampoline does not fit into a page"
+        );
+
+        let mut manager = MEMORY_MANAGER.get().unwrap().lock();
+        let base = manager.physical_memory_offset() + frame.start_address().as_u64();
+        // # Safety
+        // the frame is below 1 MiB and not used by anything else, the trampoline is only
+        // executed while it is mapped and its data is written through the physical memory
+        // mapping
+        unsafe {
+            core::ptr::copy_nonoverlapping(code.as_ptr(), base.as_mut_ptr(), code.len());
+            manager.identity_map(frame, PageTableFlags::PRESENT)?;
+        }
+
+        let parameters = base + (code.len() - size_of::<Parameters>()) as u64;
+        Ok(Self {
+            frame,
+            parameters: parameters.as_mut_ptr(),
+        })
+    }
+
+    /// The startup interrupt vector which starts a processor at the trampoline.
+    pub(super) fn vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() / 4096) as u8
+    }
+
+    /// Sets the parameters for the next processor to start.
+    ///
+    /// The processor started before must have acknowledged its parameters or must have been
+    /// stopped.
+    pub(super) fn set_parameters(&mut self, parameters: Parameters) {
+        // # Safety
+        // the parameter block is at the end of the installed trampoline
+        unsafe {
+            self.parameters.write_volatile(Parameters {
+                acknowledgement: core::ptr::addr_of!((*self.parameters).acknowledged) as u64,
+                acknowledged: 0,
+                ..parameters
+            });
+        }
+    }
+
+    /// Returns whether the started processor has read the parameters and left the trampoline.
+    pub(super) fn is_acknowledged(&self) -> bool {
+        // # Safety
+        // the flag is aligned and only written atomically by the started processor
+        unsafe { acknowledgement(core::ptr::addr_of!((*self.parameters).acknowledged) as u64) }
+            .load(Ordering::Acquire)
+            != 0
+    }
+
+    /// Removes the identity mapping, every started processor must have acknowledged its
+    /// parameters or must have been stopped.
+    pub(super) fn remove(self) {
+        MEMORY_MANAGER
+            .get()
+            .unwrap()
+            .lock()
+            .identity_unmap(self.frame);
+    }
+}
+
+impl Parameters {
+    pub(super) fn new(page_table: u64, stack_top: VirtAddr, entry: u64, argument: u64) -> Self {
+        Self {
+            page_table,
+            stack_top: stack_top.as_u64(),
+            entry,
+            argument,
+            acknowledgement: 0,
+            acknowledged: 0,
+        }
+    }
+}
+
+/// Returns the acknowledgement flag at `address`, as passed to the entry function.
+///
+/// # Safety
+/// `address` must be the address of the flag of an installed trampoline.
+pub(super) unsafe fn acknowledgement(address: u64) -> &'static AtomicU64 {
+    &*(address as *const AtomicU64)
+}
+
+// The trampoline is position independent: in real mode the code segment starts at the
+// trampoline, and the linear addresses of the descriptor table and of the long mode code are
+// computed from it. The upper halves of the registers are undefined after entering long mode.
+global_asm!(
+    r#"
+.pushsection .rodata.ap_trampoline, "a"
+.balign 16
+.globl __ap_trampoline_start
+.hidden __ap_trampoline_start
+__ap_trampoline_start:
+.Lstart:
+.code16
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+    xor %ebx, %ebx
+    mov %ax, %bx
+    shl $4, %ebx
+    lea (.Lgdt - .Lstart)(%ebx), %eax
+    mov %eax, (.Lgdt_pointer - .Lstart + 2)
+    lea (.Llong_mode - .Lstart)(%ebx), %eax
+    mov %eax, (.Lfar_pointer - .Lstart)
+    lgdtl (.Lgdt_pointer - .Lstart)
+
+    // PAE and global pages
+    mov $0xa0, %eax
+    mov %eax, %cr4
+    mov (.Lparameters - .Lstart), %eax
+    mov %eax, %cr3
+    // long mode and no-execute pages in EFER
+    mov $0xc0000080, %ecx
+    rdmsr
+    or $0x900, %eax
+    wrmsr
+    // paging, write protection and protected mode
+    mov $0x80010001, %eax
+    mov %eax, %cr0
+    ljmpl *(.Lfar_pointer - .Lstart)
+
+.code64
+.Llong_mode:
+    xor %eax, %eax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    mov %ax, %fs
+    mov %ax, %gs
+    mov %ebx, %ebx
+    mov (.Lparameters - .Lstart + 8)(%rbx), %rsp
+    mov (.Lparameters - .Lstart + 24)(%rbx), %rdi
+    mov (.Lparameters - .Lstart + 16)(%rbx), %rax
+    mov (.Lparameters - .Lstart + 32)(%rbx), %rsi
+    xor %ebp, %ebp
+    call *%rax
+    ud2
+
+.balign 8
+.Lgdt:
+    .quad 0
+    // 64 bit code segment
+    .quad 0x00af9a000000ffff
+.Lgdt_pointer:
+    .word .Lgdt_pointer - .Lgdt - 1
+    .long 0
+.Lfar_pointer:
+    .long 0
+    .word 8
+
+.balign 8
+.Lparameters:
+    .quad 0, 0, 0, 0, 0, 0
+.globl __ap_trampoline_end
+.hidden __ap_trampoline_end
+__ap_trampoline_end:
+.popsection
+"#,
+    options(att_syntax)
+);
@@ -0,0 +1,93 @@
+//! Synchronization primitives.
+//!
+//! [`IrqSpinLock`] protects data shared with interrupt handlers: it disables interrupts while it
+//! is held, so a handler on the same processor cannot spin on a lock its interrupted code holds.
+//! [`SpinLock`] is for short critical sections which interrupt handlers never wait for.
+//!
+//! [`Mutex`], [`RwLock`], [`Semaphore`] and [`Condvar`] block the waiting thread on a
+//! [`WaitQueue`] instead of spinning. They must not be used from interrupt handlers.
+//!
+//! With the `lockdep` feature the locks are checked for lock order inversions and for interrupt
+//! handlers waiting for locks which are not interrupt-safe, see the `lockdep` module.
+
+mod irq_lock;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod spin_lock;
+mod wait_queue;
+
+pub use irq_lock::{IrqSpinLock, IrqSpinLockGuard};
+pub use mutex::{Condvar, Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spin_lock::{SpinLock, SpinLockGuard};
+pub use wait_queue::WaitQueue;
+
+/// The class of a lock for the lock validation, the place where the lock was created. Locks
+/// created at the same place, e.g. the elements of an array, share their class. It is empty
+/// without the `lockdep` feature.
+#[derive(Clone, Copy)]
+struct LockClass {
+    #[cfg(feature = "lockdep")]
+    site: &'static core::panic::Location<'static>,
+}
+
+impl LockClass {
+    #[track_caller]
+    const fn new() -> Self {
+        Self {
+            #[cfg(feature = "lockdep")]
+            site: core::panic::Location::caller(),
+        }
+    }
+}
+
+/// Records a held lock for the lock validation while it exists, it is empty without the
+/// `lockdep` feature.
+struct Tracked {
+    #[cfg(feature = "lockdep")]
+    lock: usize,
+}
+
+impl Tracked {
+    /// Validates waiting for `lock` of `class` and records it as held.
+    #[track_caller]
+    fn acquire<T: ?Sized>(lock: &T, class: LockClass, irq_safe: bool) -> Self {
+        Self::record(lock, class, irq_safe, false)
+    }
+
+    /// Records `lock` of `class` as held after it has been acquired without waiting.
+    #[track_caller]
+    fn try_acquire<T: ?Sized>(lock: &T, class: LockClass, irq_safe: bool) -> Self {
+        Self::record(lock, class, irq_safe, true)
+    }
+
+    #[track_caller]
+    #[cfg_attr(not(feature = "lockdep"), allow(unused_variables))]
+    fn record<T: ?Sized>(lock: &T, class: LockClass, irq_safe: bool, try_lock: bool) -> Self {
+        #[cfg(feature = "lockdep")]
+        {
+            let lock = lock as *const T as *const () as usize;
+            lockdep::acquire(
+                lock,
+                class.site,
+                irq_safe,
+                try_lock,
+                core::panic::Location::caller(),
+            );
+            Self { lock }
+        }
+        #[cfg(not(feature = "lockdep"))]
+        Self {}
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        lockdep::release(self.lock);
+    }
+}
@@ -0,0 +1,117 @@
+use super::{LockClass, Tracked};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+
+/// A spin lock which disables interrupts on the current processor while it is held.
+///
+/// The interrupt flag is restored when the lock is released, so it may be taken with interrupts
+/// disabled, e.g. in an interrupt handler, and nested with other interrupt-safe locks.
+pub struct IrqSpinLock<T: ?Sized> {
+    class: LockClass,
+    inner: spin::Mutex<T>,
+}
+
+/// Releases the [`IrqSpinLock`] and restores the interrupt flag when dropped.
+///
+/// The guard cannot be sent to another processor, whose interrupt flag it would restore.
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    interrupts_enabled: bool,
+    _tracked: Tracked,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> IrqSpinLock<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            class: LockClass::new(),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    /// Disables interrupts and spins until the lock is acquired.
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let tracked = Tracked::acquire(self, self.class, true);
+        IrqSpinLockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+            _tracked: tracked,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Acquires the lock if it is free, interrupts are left unchanged if it is not.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<T>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSpinLockGuard {
+                guard: ManuallyDrop::new(guard),
+                interrupts_enabled,
+                _tracked: Tracked::try_acquire(self, self.class, true),
+                _not_send: PhantomData,
+            }),
+            None => {
+                if interrupts_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Releases the lock without a guard, the interrupt flag of the holder is not restored.
+    ///
+    /// # Safety
+    /// The lock holder must never access the data again, e.g. because the kernel is halting.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // # Safety
+        // the guard is not used after it has been dropped
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        // interrupts stay disabled until the lock is released
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Lock dependency validation, enabled with the `lockdep` feature.
+//!
+//! Acquiring a lock while holding others records that the held locks are taken before it. A new
+//! dependency which closes a cycle is a possible deadlock, even if the locks never actually
+//! deadlocked: processors taking the locks of the cycle in the recorded orders at the same time
+//! would. Waiting for a lock which does not disable interrupts in an interrupt handler is a
+//! possible deadlock as well, the interrupted code may hold it.
+//!
+//! The dependencies are recorded between lock classes, the places where the locks were created,
+//! so all elements of an array of locks or all locks created by the same function are checked
+//! together. Holding two locks of the same class is not checked, as there is no way to declare
+//! their order yet, but acquiring a lock which is already held is reported.
+//!
+//! An NMI or machine check may interrupt the validation on its own processor, the locks it takes
+//! are not validated then.
+//!
+//! Each problem is reported once with the call sites of the locks involved and a backtrace. The
+//! lock is acquired afterwards as usual, so an actual deadlock still hangs.
+
+use crate::backtrace::Backtrace;
+use crate::percpu::{self, MAX_CPUS};
+use crate::println;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Maximum number of lock classes, the validation stops when there are more.
+const MAX_CLASSES: usize = 256;
+/// Maximum number of locks held by a processor at the same time.
+const MAX_HELD: usize = 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
+static GRAPH: Mutex<Graph> = Mutex::new(Graph::new());
+
+// only used to initialize the array of held locks
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HELD_LOCKS: Mutex<HeldLocks> = Mutex::new(HeldLocks::new());
+
+/// Locks held by every processor, only accessed by the processor itself with interrupts disabled
+static HELD: [Mutex<HeldLocks>; MAX_CPUS] = [NO_HELD_LOCKS; MAX_CPUS];
+
+/// A bit for every lock class.
+type ClassSet = [u64; MAX_CLASSES / 64];
+
+/// Returns the number of problems reported so far.
+pub fn violations() -> usize {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+/// Stops the validation, the kernel is going down anyway.
+pub(crate) fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Validates acquiring `lock` of the class created at `class` at `location` and records it as
+/// held.
+///
+/// A lock acquired with `try_lock` did not wait, so it cannot take part in a deadlock itself.
+pub(super) fn acquire(
+    lock: usize,
+    class: &'static Location<'static>,
+    irq_safe: bool,
+    try_lock: bool,
+    location: &'static Location<'static>,
+) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let violation = interrupts::without_interrupts(|| {
+        // only an NMI or machine check interrupting the validation finds the locks in use, the
+        // graph is only locked while holding the held locks
+        let mut held = HELD[percpu::cpu_id()].try_lock()?;
+        // the locks taken for printing the report are not validated
+        if held.reporting {
+            return None;
+        }
+        let violation = GRAPH
+            .lock()
+            .check(&held, lock, class, irq_safe, try_lock, location);
+        let held_lock = Held {
+            lock,
+            class,
+            location,
+        };
+        let violation = match held.push(held_lock) {
+            Ok(()) => violation,
+            Err(()) => violation.or(Some(Violation::TooManyHeld)),
+        };
+        held.reporting = violation.is_some();
+        violation
+    });
+
+    if let Some(violation) = violation {
+        if let Violation::TooManyClasses | Violation::TooManyHeld = violation {
+            disable();
+        }
+        VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+        println!("lockdep: {violation}\n{}", Backtrace::capture());
+        interrupts::without_interrupts(|| HELD[percpu::cpu_id()].lock().reporting = false);
+    }
+}
+
+/// Removes `lock` from the locks held by the current processor.
+pub(super) fn release(lock: usize) {
+    interrupts::without_interrupts(|| {
+        // see `acquire`, the lock has not been recorded then either
+        if let Some(mut held) = HELD[percpu::cpu_id()].try_lock() {
+            held.remove(lock);
+        }
+    });
+}
+
+/// The dependencies between the lock classes.
+struct Graph {
+    /// Where the locks of every class are created
+    classes: [Option<&'static Location<'static>>; MAX_CLASSES],
+    len: usize,
+    /// Classes acquired while holding every class
+    after: [ClassSet; MAX_CLASSES],
+    /// Classes already reported as waited for in an interrupt handler
+    reported_in_interrupt: ClassSet,
+}
+
+impl Graph {
+    const fn new() -> Self {
+        Self {
+            classes: [None; MAX_CLASSES],
+            len: 0,
+            after: [[0; MAX_CLASSES / 64]; MAX_CLASSES],
+            reported_in_interrupt: [0; MAX_CLASSES / 64],
+        }
+    }
+
+    fn check(
+        &mut self,
+        held: &HeldLocks,
+        lock: usize,
+        site: &'static Location<'static>,
+        irq_safe: bool,
+        try_lock: bool,
+        location: &'static Location<'static>,
+    ) -> Option<Violation> {
+        let Some(class) = self.class(site) else {
+            return Some(Violation::TooManyClasses);
+        };
+        if try_lock {
+            return None;
+        }
+
+        if !irq_safe && percpu::in_interrupt() && !contains(&self.reported_in_interrupt, class) {
+            insert(&mut self.reported_in_interrupt, class);
+            return Some(Violation::InInterrupt {
+                lock: self.name(class),
+                location,
+            });
+        }
+
+        for held in held.iter() {
+            if held.lock == lock {
+                return Some(Violation::Recursive {
+                    lock: self.name(class),
+                    location,
+                    held_location: held.location,
+                });
+            }
+            let held_class = self.class(held.class)?;
+            if held_class == class {
+                continue;
+            }
+            // the cycle is only reported when its last dependency is new
+            if !contains(&self.after[held_class], class) {
+                insert(&mut self.after[held_class], class);
+                if self.reaches(class, held_class) {
+                    return Some(Violation::Inversion {
+                        lock: self.name(class),
+                        location,
+                        held: self.name(held_class),
+                        held_location: held.location,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the class of the locks created at `site`, it is added on first use.
+    fn class(&mut self, site: &'static Location<'static>) -> Option<usize> {
+        if let Some(class) = self.classes[..self.len]
+            .iter()
+            .position(|&other| other == Some(site))
+        {
+            return Some(class);
+        }
+        if self.len == MAX_CLASSES {
+            return None;
+        }
+        self.classes[self.len] = Some(site);
+        self.len += 1;
+        Some(self.len - 1)
+    }
+
+    fn name(&self, class: usize) -> LockName {
+        LockName {
+            site: self.classes[class].unwrap(),
+        }
+    }
+
+    /// Returns whether `to` is acquired after `from`, directly or through other classes.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited: ClassSet = [0; MAX_CLASSES / 64];
+        // every class is pushed at most once
+        let mut stack = [0u16; MAX_CLASSES];
+        let mut len = 1;
+        stack[0] = from as u16;
+        insert(&mut visited, from);
+        while len > 0 {
+            len -= 1;
+            let class = usize::from(stack[len]);
+            if class == to {
+                return true;
+            }
+            for next in 0..self.len {
+                if contains(&self.after[class], next) && !contains(&visited, next) {
+                    insert(&mut visited, next);
+                    stack[len] = next as u16;
+                    len += 1;
+                }
+            }
+        }
+        false
+    }
+}
+
+fn contains(set: &ClassSet, class: usize) -> bool {
+    set[class / 64] & 1 << (class % 64) != 0
+}
+
+fn insert(set: &mut ClassSet, class: usize) {
+    set[class / 64] |= 1 << (class % 64);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Held {
+    lock: usize,
+    class: &'static Location<'static>,
+    location: &'static Location<'static>,
+}
+
+/// The locks held by a processor in the order of their acquisition.
+struct HeldLocks {
+    locks: [Option<Held>; MAX_HELD],
+    len: usize,
+    /// A problem is being reported
+    reporting: bool,
+}
+
+impl HeldLocks {
+    const fn new() -> Self {
+        Self {
+            locks: [None; MAX_HELD],
+            len: 0,
+            reporting: false,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Held> {
+        self.locks[..self.len].iter().flatten()
+    }
+
+    fn push(&mut self, held: Held) -> Result<(), ()> {
+        let slot = self.locks.get_mut(self.len).ok_or(())?;
+        *slot = Some(held);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the most recent acquisition of `lock`, locks may be released in any order.
+    fn remove(&mut self, lock: usize) {
+        let held = &mut self.locks[..self.len];
+        if let Some(index) = held
+            .iter()
+            .rposition(|held| held.is_some_and(|held| held.lock == lock))
+        {
+            held[index..].rotate_left(1);
+            self.len -= 1;
+            self.locks[self.len] = None;
+        }
+    }
+}
+
+struct LockName {
+    site: &'static Location<'static>,
+}
+
+impl fmt::Display for LockName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock created at {}", self.site)
+    }
+}
+
+enum Violation {
+    Inversion {
+        lock: LockName,
+        location: &'static Location<'static>,
+        held: LockName,
+        held_location: &'static Location<'static>,
+    },
+    Recursive {
+        lock: LockName,
+        location: &'static Location<'static>,
+        held_location: &'static Location<'static>,
+    },
+    InInterrupt {
+        lock: LockName,
+        location: &'static Location<'static>,
+    },
+    TooManyClasses,
+    TooManyHeld,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Inversion {
+                lock,
+                location,
+                held,
+                held_location,
+            } => write!(
+                f,
+                "possible deadlock, lock order inversion\n\
+                 acquiring {lock} at {location}\n\
+                 while holding {held} acquired at {held_location}\n\
+                 which has been acquired while holding the first lock before"
+            ),
+            Violation::Recursive {
+                lock,
+                location,
+                held_location,
+            } => write!(
+                f,
+                "deadlock, acquiring {lock} at {location}\n\
+                 which is already held, acquired at {held_location}"
+            ),
+            Violation::InInterrupt { lock, location } => write!(
+                f,
+                "possible deadlock, waiting for {lock} at {location} in an interrupt handler\n\
+                 the lock does not disable interrupts, the interrupted code may hold it"
+            ),
+            Violation::TooManyClasses => write!(
+                f,
+                "more than {MAX_CLASSES} locks, the validation is disabled"
+            ),
+            Violation::TooManyHeld => write!(
+                f,
+                "more than {MAX_HELD} locks held, the validation is disabled"
+            ),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{LockClass, Tracked, WaitQueue};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// A mutual exclusion lock which blocks the waiting threads.
+pub struct Mutex<T: ?Sized> {
+    class: LockClass,
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+// # Safety
+// the value is only accessed through the guard of the single lock holder
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// Releases the [`Mutex`] when dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _tracked: Tracked,
+}
+
+// # Safety
+// the guard only gives out references to the value
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Mutex<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            class: LockClass::new(),
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Blocks until the lock is acquired.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<T> {
+        let tracked = Tracked::acquire(self, self.class, false);
+        self.waiters.wait_until(|| self.acquire());
+        MutexGuard {
+            mutex: self,
+            _tracked: tracked,
+        }
+    }
+
+    /// Acquires the lock if it is free.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if !self.acquire() {
+            return None;
+        }
+        Some(MutexGuard {
+            mutex: self,
+            _tracked: Tracked::try_acquire(self, self.class, false),
+        })
+    }
+
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // # Safety
+        // the guard holds the lock
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // # Safety
+        // the guard holds the lock
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.wake_one();
+    }
+}
+
+/// A condition variable, which lets threads wait for a change of the data protected by a
+/// [`Mutex`].
+#[derive(Debug, Default)]
+pub struct Condvar {
+    /// Number of notifications so far
+    notifications: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            notifications: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the lock of `guard`, blocks until the next notification and reacquires the
+    /// lock.
+    ///
+    /// A notification sent after the lock has been released is never missed.
+    #[track_caller]
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let notifications = self.notifications.load(Ordering::Acquire);
+        drop(guard);
+        self.waiters
+            .wait_until(|| self.notifications.load(Ordering::Acquire) != notifications);
+        mutex.lock()
+    }
+
+    /// Blocks while `condition` returns `true` for the protected value.
+    #[track_caller]
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes one waiting thread.
+    pub fn notify_one(&self) {
+        self.notifications.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    /// Wakes all waiting threads.
+    pub fn notify_all(&self) {
+        self.notifications.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
@@ -0,0 +1,161 @@
+use super::{LockClass, Tracked, WaitQueue};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Set in the state while a writer holds the lock, the other bits count the readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+/// A reader-writer lock which blocks the waiting threads.
+///
+/// Any number of readers or a single writer may hold the lock. Readers are preferred, a steady
+/// stream of them can starve writers.
+pub struct RwLock<T: ?Sized> {
+    class: LockClass,
+    state: AtomicUsize,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+// # Safety
+// readers share references to the value, the single writer has exclusive access
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+/// Releases a shared lock of the [`RwLock`] when dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _tracked: Tracked,
+}
+
+/// Releases the exclusive lock of the [`RwLock`] when dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _tracked: Tracked,
+}
+
+impl<T> RwLock<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            class: LockClass::new(),
+            state: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Blocks until a shared lock is acquired.
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let tracked = Tracked::acquire(self, self.class, false);
+        self.waiters.wait_until(|| self.acquire_shared());
+        RwLockReadGuard {
+            lock: self,
+            _tracked: tracked,
+        }
+    }
+
+    /// Acquires a shared lock if no writer holds the lock.
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        if !self.acquire_shared() {
+            return None;
+        }
+        Some(RwLockReadGuard {
+            lock: self,
+            _tracked: Tracked::try_acquire(self, self.class, false),
+        })
+    }
+
+    /// Blocks until the exclusive lock is acquired.
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let tracked = Tracked::acquire(self, self.class, false);
+        self.waiters.wait_until(|| self.acquire_exclusive());
+        RwLockWriteGuard {
+            lock: self,
+            _tracked: tracked,
+        }
+    }
+
+    /// Acquires the exclusive lock if the lock is free.
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if !self.acquire_exclusive() {
+            return None;
+        }
+        Some(RwLockWriteGuard {
+            lock: self,
+            _tracked: Tracked::try_acquire(self, self.class, false),
+        })
+    }
+
+    fn acquire_shared(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                (state & WRITER == 0).then_some(state + 1)
+            })
+            .is_ok()
+    }
+
+    fn acquire_exclusive(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // # Safety
+        // no writer holds the lock while the guard exists
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // only a writer waits for the last reader
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.waiters.wake_all();
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // # Safety
+        // the guard holds the exclusive lock
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // # Safety
+        // the guard holds the exclusive lock
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.waiters.wake_all();
+    }
+}
@@ -0,0 +1,83 @@
+use super::{LockClass, Tracked};
+use core::ops::{Deref, DerefMut};
+
+/// A spin lock for data which is not shared with interrupt handlers.
+///
+/// It must not be waited for in an interrupt handler, as the handler could spin on a lock held
+/// by the code it interrupted. [`try_lock`](Self::try_lock) is fine in any context.
+pub struct SpinLock<T: ?Sized> {
+    class: LockClass,
+    inner: spin::Mutex<T>,
+}
+
+/// Releases the [`SpinLock`] when dropped.
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    guard: spin::MutexGuard<'a, T>,
+    _tracked: Tracked,
+}
+
+impl<T> SpinLock<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            class: LockClass::new(),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Spins until the lock is acquired.
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        let tracked = Tracked::acquire(self, self.class, false);
+        SpinLockGuard {
+            guard: self.inner.lock(),
+            _tracked: tracked,
+        }
+    }
+
+    /// Acquires the lock if it is free.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        let guard = self.inner.try_lock()?;
+        Some(SpinLockGuard {
+            guard,
+            _tracked: Tracked::try_acquire(self, self.class, false),
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Releases the lock without a guard.
+    ///
+    /// # Safety
+    /// The lock holder must never access the data again, e.g. because the kernel is halting.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
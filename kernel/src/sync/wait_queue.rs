@@ -0,0 +1,63 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+/// Threads waiting for a condition to become true.
+///
+/// The waiters re-check their condition after every wakeup, so spurious wakeups are harmless.
+/// There is no scheduler yet, a waiting processor halts until the next interrupt instead of
+/// switching to another thread. The timer interrupts bound the delay of a wakeup from another
+/// processor.
+#[derive(Debug, Default)]
+pub struct WaitQueue {
+    /// Number of wakeups so far, a waiter only blocks if it has not changed since it last
+    /// checked its condition
+    wakeups: AtomicU64,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            wakeups: AtomicU64::new(0),
+        }
+    }
+
+    /// Blocks until `condition` returns `true`.
+    ///
+    /// With interrupts disabled the processor cannot halt and spins instead.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let wakeups = self.wakeups.load(Ordering::Acquire);
+            if condition() {
+                return;
+            }
+            self.block(wakeups);
+        }
+    }
+
+    /// Wakes a single waiter.
+    pub fn wake_one(&self) {
+        // without threads the waiters cannot be told apart
+        self.wake_all();
+    }
+
+    /// Wakes all waiters.
+    pub fn wake_all(&self) {
+        self.wakeups.fetch_add(1, Ordering::Release);
+    }
+
+    /// Blocks until the next wakeup after `wakeups`.
+    fn block(&self, wakeups: u64) {
+        if !interrupts::are_enabled() {
+            core::hint::spin_loop();
+            return;
+        }
+        // a wakeup by an interrupt between the check and `hlt` would be missed otherwise, `sti`
+        // only enables interrupts after the following instruction
+        interrupts::disable();
+        if self.wakeups.load(Ordering::Acquire) == wakeups {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use core::ptr;
-
+use crate::memory::MEMORY_MANAGER;
+use alloc::{vec, vec::Vec};
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
 
-#[derive(Debug, Clone, Copy)]
+pub mod bmp;
+mod draw;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -10,6 +13,7 @@ pub struct Color {
 }
 
 impl Color {
+    pub const BLACK: Color = Color::new(0x00, 0x00, 0x00);
     pub const GREEN: Color = Color::new(0x00, 0xFF, 0x00);
 
     pub const fn new(r: u8, g: u8, b: u8) -> Self {
@@ -21,19 +25,164 @@ impl Color {
         let (r, g, b) = (f32::from(self.r), f32::from(self.g), f32::from(self.b));
         (0.299 * r + 0.587 * g + 0.114 * b) as u8
     }
+
+    /// Linearly interpolates between `from` and `to`, `amount` of 255 yields `to`
+    pub fn mix(from: Color, to: Color, amount: u8) -> Self {
+        let mix_channel = |from: u8, to: u8| {
+            let (from, to, amount) = (u16::from(from), u16::from(to), u16::from(amount));
+            ((from * (255 - amount) + to * amount) / 255) as u8
+        };
+        Self::new(
+            mix_channel(from.r, to.r),
+            mix_channel(from.g, to.g),
+            mix_channel(from.b, to.b),
+        )
+    }
+}
+
+/// Color with an alpha channel, alpha of 255 is fully opaque.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
 }
 
+impl Rgba {
+    pub const TRANSPARENT: Rgba = Rgba::new(0x00, 0x00, 0x00, 0x00);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn color(self) -> Color {
+        Color::new(self.r, self.g, self.b)
+    }
+}
+
+impl From<Color> for Rgba {
+    fn from(color: Color) -> Self {
+        Self::new(color.r, color.g, color.b, 0xFF)
+    }
+}
+
+/// RGBA image stored row by row, top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgba>,
+}
+
+impl Image {
+    /// # Panics
+    /// The function will panic if `pixels` does not contain exactly `width * height` pixels.
+    pub fn new(width: usize, height: usize, pixels: Vec<Rgba>) -> Self {
+        assert_eq!(pixels.len(), width * height, "invalid image size");
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Rgba {
+        self.pixels[x + y * self.width]
+    }
+}
+
+/// Axis-aligned rectangle in screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub const fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub const fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    /// Returns the common part of both rectangles or [`None`] if they do not overlap.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (x < right && y < bottom).then(|| Rect::new(x, y, right - x, bottom - y))
+    }
+}
+
+/// Double buffered framebuffer writer.
+///
+/// All drawing goes to a back buffer, which is copied to the video memory with
+/// [`Writer::present`] or [`Writer::flush`]. The back buffer is mapped from frames of its own, as
+/// large framebuffers do not fit into the kernel heap. The writer keeps track of the modified part of
+/// every scanline so that presenting only copies what has actually changed.
 #[derive(Debug)]
 pub struct Writer<'a> {
     buffer: &'a mut [u8],
+    back_buffer: &'static mut [u8],
+    /// Range of columns of each scanline modified since the last present
+    dirty: Vec<Option<(usize, usize)>>,
     info: FrameBufferInfo,
+    encoding: PixelEncoding,
 }
 
 impl<'a> Writer<'a> {
+    /// # Panics
+    /// The function will panic if the memory manager is not initialized or there is not enough
+    /// memory for the back buffer.
     pub fn new(framebuffer: &'a mut FrameBuffer) -> Self {
+        let mut info = framebuffer.info();
+        // never address memory past the end of the framebuffer, even if the reported mode is
+        // inconsistent
+        let row_size = info.stride * info.bytes_per_pixel;
+        if row_size > 0 {
+            info.height = info.height.min(info.byte_len / row_size);
+        }
+        info.width = info.width.min(info.stride);
+
+        let back_buffer = MEMORY_MANAGER
+            .get()
+            .expect("memory manager is not initialized")
+            .lock()
+            .allocate_buffer(info.byte_len)
+            .expect("failed to allocate the back buffer");
         Self {
-            info: framebuffer.info(),
             buffer: framebuffer.buffer_mut(),
+            // # Safety
+            // the buffer is mapped for the writer alone and never freed
+            back_buffer: unsafe {
+                core::slice::from_raw_parts_mut(back_buffer.as_mut_ptr(), info.byte_len)
+            },
+            dirty: vec![None; info.height],
+            encoding: PixelEncoding::new(&info),
+            info,
         }
     }
 
@@ -45,34 +194,230 @@ impl<'a> Writer<'a> {
         self.info.height
     }
 
+    /// Bounds of the screen
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
     pub fn clear(&mut self) {
-        self.buffer.fill(0x00);
+        self.back_buffer.fill(0x00);
+        self.mark_dirty(self.bounds());
     }
 
+    /// Writes a pixel to the back buffer, pixels outside of the screen are ignored.
     pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
-        let offset = x + y * self.info.stride;
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [color.r, color.g, color.b, 0],
-            PixelFormat::Bgr => [color.b, color.g, color.r, 0],
-            PixelFormat::U8 => [color.greyscale(), 0, 0, 0],
-            unknown => {
-                // set a supported (but invalid) pixel format before panicking to avoid a double
-                // panic; it might not be readable though
-                self.info.pixel_format = PixelFormat::Rgb;
-                panic!("pixel format {:?} not supported in vga writer", unknown)
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+        let bytes_per_pixel = self.pixel_size();
+        let color = self.encoding.encode(color);
+        let byte_offset = (x + y * self.info.stride) * self.info.bytes_per_pixel;
+        self.back_buffer[byte_offset..(byte_offset + bytes_per_pixel)]
+            .copy_from_slice(&color[..bytes_per_pixel]);
+        self.mark_dirty(Rect::new(x, y, 1, 1));
+    }
+
+    /// Reads back a pixel from the back buffer.
+    /// Returns [`None`] if the pixel lies outside of the screen.
+    pub fn read_pixel(&self, x: usize, y: usize) -> Option<Color> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        let bytes_per_pixel = self.pixel_size();
+        let byte_offset = (x + y * self.info.stride) * self.info.bytes_per_pixel;
+        let mut pixel = [0; 8];
+        pixel[..bytes_per_pixel]
+            .copy_from_slice(&self.back_buffer[byte_offset..(byte_offset + bytes_per_pixel)]);
+        Some(self.encoding.decode(pixel))
+    }
+
+    /// Fills the part of `rect` that lies on the screen with `color`.
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let Some(rect) = rect.intersection(&self.bounds()) else {
+            return;
+        };
+        let bytes_per_pixel = self.pixel_size();
+        let color = self.encoding.encode(color);
+        for y in rect.y..rect.bottom() {
+            let row = self.row_range(y, rect.x, rect.right());
+            for pixel in self.back_buffer[row].chunks_exact_mut(self.info.bytes_per_pixel) {
+                pixel[..bytes_per_pixel].copy_from_slice(&color[..bytes_per_pixel]);
             }
+        }
+        self.mark_dirty(rect);
+    }
+
+    /// Copies every region modified since the last call to the video memory.
+    pub fn present(&mut self) {
+        for y in 0..self.dirty.len() {
+            if let Some((start, end)) = self.dirty[y].take() {
+                self.copy_row(y, start, end);
+            }
+        }
+    }
+
+    /// Copies the part of `rect` that lies on the screen to the video memory.
+    pub fn flush(&mut self, rect: Rect) {
+        let Some(rect) = rect.intersection(&self.bounds()) else {
+            return;
         };
+        for y in rect.y..rect.bottom() {
+            self.copy_row(y, rect.x, rect.right());
+        }
+    }
+
+    fn copy_row(&mut self, y: usize, start: usize, end: usize) {
+        let row = self.row_range(y, start, end);
+        self.buffer[row.clone()].copy_from_slice(&self.back_buffer[row]);
+    }
+
+    /// Byte range of the pixels from `start` to `end` (exclusive) of the `y`-th scanline
+    fn row_range(&self, y: usize, start: usize, end: usize) -> core::ops::Range<usize> {
+        let row_offset = y * self.info.stride;
         let bytes_per_pixel = self.info.bytes_per_pixel;
-        let byte_offset = offset * bytes_per_pixel;
-        self.buffer[byte_offset..(byte_offset + bytes_per_pixel)]
-            .copy_from_slice(&color[..bytes_per_pixel]);
-        let _ = unsafe { ptr::read_volatile(&self.buffer[byte_offset]) };
+        (row_offset + start) * bytes_per_pixel..(row_offset + end) * bytes_per_pixel
     }
 
-    pub fn shift_up(&mut self, npixels: usize) {
-        let offset = self.info.bytes_per_pixel * self.width() * npixels;
-        self.buffer.copy_within(offset.., 0);
-        let len = self.buffer.len();
-        self.buffer[len - offset..].fill(0x00);
+    fn mark_dirty(&mut self, rect: Rect) {
+        for dirty in &mut self.dirty[rect.y..rect.bottom()] {
+            *dirty = match *dirty {
+                Some((start, end)) => Some((start.min(rect.x), end.max(rect.right()))),
+                None => Some((rect.x, rect.right())),
+            };
+        }
+    }
+
+    /// Number of bytes of each pixel that carry the color
+    fn pixel_size(&self) -> usize {
+        self.info.bytes_per_pixel.min(8)
+    }
+}
+
+/// Position and width of a color channel inside of a pixel.
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    const fn new(shift: u32, bits: u32) -> Self {
+        Self { shift, bits }
+    }
+
+    fn encode(self, value: u8) -> u64 {
+        let value = u64::from(value);
+        let value = if self.bits >= 8 {
+            value << (self.bits - 8)
+        } else {
+            value >> (8 - self.bits)
+        };
+        value.checked_shl(self.shift).unwrap_or(0)
+    }
+
+    fn decode(self, pixel: u64) -> u8 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let mask = u64::MAX >> (64 - self.bits.min(64));
+        let value = pixel.checked_shr(self.shift).unwrap_or(0) & mask;
+        if self.bits >= 8 {
+            (value >> (self.bits - 8)) as u8
+        } else {
+            (value * 0xFF / mask) as u8
+        }
+    }
+}
+
+/// Describes how a [`Color`] is stored in the framebuffer.
+#[derive(Debug, Clone, Copy)]
+enum PixelEncoding {
+    Greyscale(Channel),
+    Rgb {
+        red: Channel,
+        green: Channel,
+        blue: Channel,
+    },
+}
+
+impl PixelEncoding {
+    fn new(info: &FrameBufferInfo) -> Self {
+        let byte = |index: u32| Channel::new(index * 8, 8);
+        match info.pixel_format {
+            PixelFormat::Bgr => PixelEncoding::Rgb {
+                red: byte(2),
+                green: byte(1),
+                blue: byte(0),
+            },
+            PixelFormat::U8 => PixelEncoding::Greyscale(byte(0)),
+            PixelFormat::Unknown {
+                red_position,
+                green_position,
+                blue_position,
+            } => {
+                let pixel_bits = (info.bytes_per_pixel.min(8) * 8) as u32;
+                let positions = [red_position, green_position, blue_position].map(u32::from);
+                // only the channel offsets are known, assume that every channel spans up to the
+                // next one (or to the end of the pixel), but at most 8 bits
+                let widths = positions.map(|position| {
+                    let end = positions
+                        .iter()
+                        .copied()
+                        .filter(|&other| other > position)
+                        .min()
+                        .unwrap_or(pixel_bits)
+                        .min(pixel_bits);
+                    end.saturating_sub(position).min(8)
+                });
+                // the end of the pixel may be padding, as in xRGB or xRGB 1555, so the topmost
+                // channel is no wider than the others
+                let top = (0..3).max_by_key(|&index| positions[index]).unwrap();
+                let others = (0..3).filter(|&index| index != top);
+                let widest_other = others.map(|index| widths[index]).max().unwrap();
+                let channel = |index: usize| {
+                    let width = if index == top {
+                        widths[index].min(widest_other)
+                    } else {
+                        widths[index]
+                    };
+                    Channel::new(positions[index], width)
+                };
+                PixelEncoding::Rgb {
+                    red: channel(0),
+                    green: channel(1),
+                    blue: channel(2),
+                }
+            }
+            // `Rgb` and any format added to the bootloader in the future
+            _ => PixelEncoding::Rgb {
+                red: byte(0),
+                green: byte(1),
+                blue: byte(2),
+            },
+        }
+    }
+
+    /// Returns the little endian bytes of the pixel.
+    fn encode(self, color: Color) -> [u8; 8] {
+        let pixel = match self {
+            PixelEncoding::Greyscale(channel) => channel.encode(color.greyscale()),
+            PixelEncoding::Rgb { red, green, blue } => {
+                red.encode(color.r) | green.encode(color.g) | blue.encode(color.b)
+            }
+        };
+        pixel.to_le_bytes()
+    }
+
+    fn decode(self, pixel: [u8; 8]) -> Color {
+        let pixel = u64::from_le_bytes(pixel);
+        match self {
+            PixelEncoding::Greyscale(channel) => {
+                let value = channel.decode(pixel);
+                Color::new(value, value, value)
+            }
+            PixelEncoding::Rgb { red, green, blue } => {
+                Color::new(red.decode(pixel), green.decode(pixel), blue.decode(pixel))
+            }
+        }
     }
 }
@@ -0,0 +1,192 @@
+//! Decoder for uncompressed Windows bitmap (BMP) images.
+//!
+//! Supports 8-bit palette based images and 24/32-bit images, both bottom-up and top-down.
+//! 32-bit images may use `BI_BITFIELDS` channel masks, including an alpha mask.
+
+use super::{Image, Rgba};
+use alloc::vec::Vec;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The data does not start with the `BM` signature
+    InvalidSignature,
+    /// The data ends before all headers and pixels could be read
+    UnexpectedEnd,
+    /// The info header is older than `BITMAPINFOHEADER`
+    UnsupportedHeader(u32),
+    UnsupportedBitDepth(u16),
+    UnsupportedCompression(u32),
+    InvalidDimensions,
+}
+
+/// Decodes a BMP file into an [`Image`].
+pub fn decode(data: &[u8]) -> Result<Image, Error> {
+    if data.get(..2) != Some(b"BM") {
+        return Err(Error::InvalidSignature);
+    }
+    let pixels_offset = read_u32(data, 10)? as usize;
+
+    let header_size = read_u32(data, FILE_HEADER_SIZE)?;
+    if header_size < INFO_HEADER_SIZE {
+        return Err(Error::UnsupportedHeader(header_size));
+    }
+    let width = read_u32(data, FILE_HEADER_SIZE + 4)? as i32;
+    let height = read_u32(data, FILE_HEADER_SIZE + 8)? as i32;
+    let bit_depth = read_u16(data, FILE_HEADER_SIZE + 14)?;
+    let compression = read_u32(data, FILE_HEADER_SIZE + 16)?;
+    let palette_size = read_u32(data, FILE_HEADER_SIZE + 32)?;
+
+    if width <= 0 || height == 0 {
+        return Err(Error::InvalidDimensions);
+    }
+    // positive height means that rows are stored from the bottom to the top
+    let bottom_up = height > 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+    let format = match (bit_depth, compression) {
+        (8, BI_RGB) => {
+            let colors = if palette_size == 0 {
+                256
+            } else {
+                palette_size as usize
+            };
+            let palette_offset = FILE_HEADER_SIZE + header_size as usize;
+            let palette_end = colors
+                .checked_mul(4)
+                .and_then(|size| size.checked_add(palette_offset))
+                .ok_or(Error::InvalidDimensions)?;
+            let palette = data
+                .get(palette_offset..palette_end)
+                .ok_or(Error::UnexpectedEnd)?;
+            PixelLayout::Palette(palette)
+        }
+        // the fourth byte of 32-bit pixels is unused
+        (24 | 32, BI_RGB) => PixelLayout::Masks(ChannelMasks::BGR),
+        (32, BI_BITFIELDS) => {
+            let mask = |index| read_u32(data, FILE_HEADER_SIZE + INFO_HEADER_SIZE as usize + index);
+            let alpha = if header_size > INFO_HEADER_SIZE {
+                mask(12)?
+            } else {
+                0
+            };
+            PixelLayout::Masks(ChannelMasks {
+                red: mask(0)?,
+                green: mask(4)?,
+                blue: mask(8)?,
+                alpha,
+            })
+        }
+        (8 | 24 | 32, compression) => return Err(Error::UnsupportedCompression(compression)),
+        (bit_depth, _) => return Err(Error::UnsupportedBitDepth(bit_depth)),
+    };
+
+    let bytes_per_pixel = usize::from(bit_depth / 8);
+    // rows are padded to a multiple of 4 bytes
+    let row_size = width
+        .checked_mul(bytes_per_pixel)
+        .and_then(|size| size.checked_add(3))
+        .ok_or(Error::InvalidDimensions)?
+        & !3;
+    let pixels_end = row_size
+        .checked_mul(height)
+        .and_then(|size| size.checked_add(pixels_offset))
+        .ok_or(Error::InvalidDimensions)?;
+    let pixel_data = data
+        .get(pixels_offset..pixels_end)
+        .ok_or(Error::UnexpectedEnd)?;
+
+    // the pixel data is present, so the image is at most as large as the file
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if bottom_up { height - 1 - y } else { y };
+        let row = &pixel_data[row * row_size..][..width * bytes_per_pixel];
+        pixels.extend(
+            row.chunks_exact(bytes_per_pixel)
+                .map(|pixel| format.decode(pixel)),
+        );
+    }
+
+    Ok(Image::new(width, height, pixels))
+}
+
+enum PixelLayout<'a> {
+    /// 8-bit indices into a table of BGRX colors
+    Palette(&'a [u8]),
+    /// Little endian pixels with channels selected by bit masks
+    Masks(ChannelMasks),
+}
+
+impl PixelLayout<'_> {
+    fn decode(&self, pixel: &[u8]) -> Rgba {
+        match self {
+            PixelLayout::Palette(palette) => {
+                match palette.get(usize::from(pixel[0]) * 4..usize::from(pixel[0]) * 4 + 3) {
+                    Some(&[b, g, r]) => Rgba::new(r, g, b, 0xFF),
+                    _ => Rgba::TRANSPARENT,
+                }
+            }
+            PixelLayout::Masks(masks) => {
+                let mut bytes = [0; 4];
+                bytes[..pixel.len()].copy_from_slice(pixel);
+                masks.decode(u32::from_le_bytes(bytes))
+            }
+        }
+    }
+}
+
+struct ChannelMasks {
+    red: u32,
+    green: u32,
+    blue: u32,
+    alpha: u32,
+}
+
+impl ChannelMasks {
+    const BGR: ChannelMasks = ChannelMasks {
+        red: 0x00FF_0000,
+        green: 0x0000_FF00,
+        blue: 0x0000_00FF,
+        alpha: 0,
+    };
+
+    fn decode(&self, value: u32) -> Rgba {
+        let alpha = if self.alpha == 0 {
+            0xFF
+        } else {
+            extract_channel(value, self.alpha)
+        };
+        Rgba::new(
+            extract_channel(value, self.red),
+            extract_channel(value, self.green),
+            extract_channel(value, self.blue),
+            alpha,
+        )
+    }
+}
+
+/// Extracts the channel selected by `mask` and scales it to 8 bits.
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones();
+    let channel = (value & mask) >> mask.trailing_zeros();
+    let max = (1u64 << bits) - 1;
+    (u64::from(channel) * 0xFF / max) as u8
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    let bytes = data.get(offset..offset + 2).ok_or(Error::UnexpectedEnd)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    let bytes = data.get(offset..offset + 4).ok_or(Error::UnexpectedEnd)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
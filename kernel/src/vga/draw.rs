@@ -0,0 +1,206 @@
+//! 2D drawing primitives.
+//!
+//! Every primitive is clipped to the framebuffer bounds, so shapes may lie partially (or
+//! completely) outside of the screen.
+
+use super::{Color, Image, Rect, Rgba, Writer};
+
+impl Writer<'_> {
+    /// Draws the outline of `rect`.
+    pub fn draw_rect(&mut self, rect: Rect, color: Color) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let (right, bottom) = (rect.right() - 1, rect.bottom() - 1);
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, bottom, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.y, 1, rect.height), color);
+        self.fill_rect(Rect::new(right, rect.y, 1, rect.height), color);
+    }
+
+    /// Draws a line from (`x0`, `y0`) to (`x1`, `y1`) using Bresenham's algorithm.
+    pub fn draw_line(&mut self, start: (isize, isize), end: (isize, isize), color: Color) {
+        let Some(((x0, y0), (x1, y1))) = self.clip_line(start, end) else {
+            return;
+        };
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.put_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled_error = 2 * error;
+            if doubled_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws the outline of a circle using the midpoint algorithm.
+    pub fn draw_circle(&mut self, (cx, cy): (isize, isize), radius: isize, color: Color) {
+        self.for_each_octant_point(radius, |writer, x, y| {
+            for (px, py) in [(x, y), (y, x), (-y, x), (-x, y)] {
+                writer.put_pixel(cx + px, cy + py, color);
+                writer.put_pixel(cx - px, cy - py, color);
+            }
+        });
+    }
+
+    /// Draws a filled circle.
+    pub fn fill_circle(&mut self, (cx, cy): (isize, isize), radius: isize, color: Color) {
+        self.for_each_octant_point(radius, |writer, x, y| {
+            writer.fill_span(cx - x, cx + x, cy + y, color);
+            writer.fill_span(cx - x, cx + x, cy - y, color);
+            writer.fill_span(cx - y, cx + y, cy + x, color);
+            writer.fill_span(cx - y, cx + y, cy - x, color);
+        });
+    }
+
+    /// Blends `color` over the pixel at (`x`, `y`) according to its alpha channel.
+    pub fn blend_pixel(&mut self, x: isize, y: isize, color: Rgba) {
+        let Some((x, y)) = self.clip(x, y) else {
+            return;
+        };
+        match color.a {
+            0x00 => {}
+            0xFF => self.write_pixel(x, y, color.color()),
+            alpha => {
+                let background = self.read_pixel(x, y).unwrap_or(Color::BLACK);
+                self.write_pixel(x, y, Color::mix(background, color.color(), alpha));
+            }
+        }
+    }
+
+    /// Draws `image` with its top left corner at (`x`, `y`), blending it over the current
+    /// contents of the screen.
+    pub fn blit(&mut self, image: &Image, x: isize, y: isize) {
+        for image_y in 0..image.height() {
+            for image_x in 0..image.width() {
+                let pixel = image.pixel(image_x, image_y);
+                self.blend_pixel(x + image_x as isize, y + image_y as isize, pixel);
+            }
+        }
+    }
+
+    /// Calls `f` with every point of the first octant of a circle of given `radius`.
+    fn for_each_octant_point(&mut self, radius: isize, mut f: impl FnMut(&mut Self, isize, isize)) {
+        if radius < 0 {
+            return;
+        }
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+        while x >= y {
+            f(self, x, y);
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// Fills the horizontal span from `x0` to `x1` (inclusive) of the `y`-th scanline.
+    fn fill_span(&mut self, x0: isize, x1: isize, y: isize, color: Color) {
+        if y < 0 || x1 < 0 || x1 < x0 {
+            return;
+        }
+        let x0 = x0.max(0) as usize;
+        let width = x1 as usize - x0 + 1;
+        self.fill_rect(Rect::new(x0, y as usize, width, 1), color);
+    }
+
+    fn put_pixel(&mut self, x: isize, y: isize, color: Color) {
+        if let Some((x, y)) = self.clip(x, y) {
+            self.write_pixel(x, y, color);
+        }
+    }
+
+    /// Clips the segment to [`Writer::bounds`] using the Cohen–Sutherland algorithm, returns
+    /// [`None`] if it lies completely outside of the screen.
+    fn clip_line(
+        &self,
+        (x0, y0): (isize, isize),
+        (x1, y1): (isize, isize),
+    ) -> Option<((isize, isize), (isize, isize))> {
+        const LEFT: u8 = 1 << 0;
+        const RIGHT: u8 = 1 << 1;
+        const TOP: u8 = 1 << 2;
+        const BOTTOM: u8 = 1 << 3;
+
+        let bounds = self.bounds();
+        if bounds.width == 0 || bounds.height == 0 {
+            return None;
+        }
+        // the products below do not overflow for any pair of `isize` coordinates
+        let (right, bottom) = (bounds.right() as i128 - 1, bounds.bottom() as i128 - 1);
+        let outcode = |x: i128, y: i128| {
+            let horizontal = match x {
+                _ if x < 0 => LEFT,
+                _ if x > right => RIGHT,
+                _ => 0,
+            };
+            let vertical = match y {
+                _ if y < 0 => TOP,
+                _ if y > bottom => BOTTOM,
+                _ => 0,
+            };
+            horizontal | vertical
+        };
+
+        let (mut start, mut end) = ((x0 as i128, y0 as i128), (x1 as i128, y1 as i128));
+        let (mut start_code, mut end_code) = (outcode(start.0, start.1), outcode(end.0, end.1));
+        loop {
+            if start_code | end_code == 0 {
+                // both endpoints are on the screen now
+                let point = |(x, y): (i128, i128)| (x as isize, y as isize);
+                return Some((point(start), point(end)));
+            }
+            if start_code & end_code != 0 {
+                return None;
+            }
+
+            // move an endpoint outside of the screen onto the edge it lies beyond, the
+            // divisions are safe since the endpoints lie on different sides of that edge
+            let code = if start_code != 0 {
+                start_code
+            } else {
+                end_code
+            };
+            let ((x0, y0), (x1, y1)) = (start, end);
+            let point = if code & TOP != 0 {
+                (x0 + (x1 - x0) * -y0 / (y1 - y0), 0)
+            } else if code & BOTTOM != 0 {
+                (x0 + (x1 - x0) * (bottom - y0) / (y1 - y0), bottom)
+            } else if code & RIGHT != 0 {
+                (right, y0 + (y1 - y0) * (right - x0) / (x1 - x0))
+            } else {
+                (0, y0 + (y1 - y0) * -x0 / (x1 - x0))
+            };
+            if code == start_code {
+                start = point;
+                start_code = outcode(point.0, point.1);
+            } else {
+                end = point;
+                end_code = outcode(point.0, point.1);
+            }
+        }
+    }
+
+    fn clip(&self, x: isize, y: isize) -> Option<(usize, usize)> {
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        (x < self.width() && y < self.height()).then_some((x, y))
+    }
+}
@@ -0,0 +1,87 @@
+//! Symbol table embedded into kernel executables for backtraces, shared by the build script and
+//! the integration test runner.
+
+use std::path::Path;
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::{Entry, Type};
+use xmas_elf::ElfFile;
+
+/// Size of the `.ksyms` section, it must match `SYMBOL_TABLE_SIZE` in `kernel/src/backtrace.rs`.
+const SYMBOL_TABLE_SIZE: usize = 512 * 1024;
+
+/// Copies the kernel executable to `output` with its function symbols written into the `.ksyms`
+/// section, see `kernel/src/backtrace.rs` for the format.
+///
+/// Returns the number of symbols omitted because the section is full.
+pub fn embed_symbol_table(kernel: &Path, output: &Path) -> usize {
+    let mut data = std::fs::read(kernel).unwrap();
+    let elf = ElfFile::new(&data).unwrap();
+
+    let section = elf
+        .find_section_by_name(".ksyms")
+        .expect("kernel has no .ksyms section");
+    let base = section.address();
+    let offset = usize::try_from(section.offset()).unwrap();
+    assert_eq!(
+        section.size(),
+        SYMBOL_TABLE_SIZE as u64,
+        "unexpected .ksyms size"
+    );
+
+    let mut symbols = Vec::new();
+    for section in elf.section_iter() {
+        let Ok(SectionData::SymbolTable64(entries)) = section.get_data(&elf) else {
+            continue;
+        };
+        for entry in entries {
+            if entry.get_type() != Ok(Type::Func) || entry.value() == 0 {
+                continue;
+            }
+            let Ok(name) = entry.get_name(&elf) else {
+                continue;
+            };
+            let name = format!("{:#}", rustc_demangle::demangle(name));
+            symbols.push((entry.value(), entry.size(), name));
+        }
+    }
+    symbols.sort();
+    symbols.dedup_by_key(|(address, ..)| *address);
+
+    let (table, omitted) = encode_symbol_table(base, &symbols);
+    data[offset..offset + table.len()].copy_from_slice(&table);
+    std::fs::write(output, data).unwrap();
+    omitted
+}
+
+/// Returns the table and the number of symbols which did not fit.
+fn encode_symbol_table(base: u64, symbols: &[(u64, u64, String)]) -> (Vec<u8>, usize) {
+    const HEADER_SIZE: usize = 16;
+    const ENTRY_SIZE: usize = 20;
+
+    // keep as many symbols as fit in the section
+    let mut count = 0;
+    let mut size = HEADER_SIZE;
+    for (.., name) in symbols {
+        if size + ENTRY_SIZE + name.len() > SYMBOL_TABLE_SIZE {
+            break;
+        }
+        size += ENTRY_SIZE + name.len();
+        count += 1;
+    }
+    let kept = &symbols[..count];
+
+    let mut table = Vec::with_capacity(size);
+    let mut names = Vec::new();
+    table.extend_from_slice(b"KSYM");
+    table.extend_from_slice(&u32::try_from(count).unwrap().to_le_bytes());
+    table.extend_from_slice(&base.to_le_bytes());
+    for (address, size, name) in kept {
+        table.extend_from_slice(&address.to_le_bytes());
+        table.extend_from_slice(&u32::try_from(*size).unwrap_or(u32::MAX).to_le_bytes());
+        table.extend_from_slice(&u32::try_from(names.len()).unwrap().to_le_bytes());
+        table.extend_from_slice(&u32::try_from(name.len()).unwrap().to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+    }
+    table.extend_from_slice(&names);
+    (table, symbols.len() - count)
+}
@@ -1,3 +1,5 @@
+#[path = "../../ksyms.rs"]
+mod ksyms;
 mod runner;
 
 test!(basic);
@@ -5,3 +7,25 @@ test!(handle_stack_overflow);
 test!(handle_breakpoint);
 test!(handle_page_fault);
 test!(frame_allocation);
+test!(print_reentrancy);
+test!(scrollback);
+test!(demand_paging);
+test!(fork);
+test!(slab_cache);
+test!(huge_pages);
+test!(wx_protection);
+test!(user_access);
+test!(smp, "-smp", "4");
+test!(per_cpu, "-smp", "2");
+test!(ipi, "-smp", "4");
+test!(sync, "-smp", "2");
+test!(lockdep, "-smp", "2");
+test!(irq);
+test!(deferred_work);
+test!(interrupt_stats, "-smp", "2");
+test!(spurious_irq);
+test!(nmi);
+test!(pixel_formats);
+test!(backtrace);
+test!(graphics);
+test!(missing_glyph);
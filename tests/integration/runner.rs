@@ -1,3 +1,4 @@
+use crate::ksyms::embed_symbol_table;
 use bootloader::{BootConfig, DiskImageBuilder};
 use std::{
     io,
@@ -6,23 +7,27 @@ use std::{
     thread,
 };
 
-/// Creates a test case that runs a binary form `test_kernel` crate in qemu.
+/// Creates a test case that runs a binary form `test_kernel` crate in qemu, optionally with
+/// additional qemu arguments.
 #[macro_export]
 macro_rules! test {
-    ($bin_name:ident) => {
+    ($bin_name:ident $(, $arg:literal)*) => {
         #[test]
         fn $bin_name() {
-            $crate::runner::run(env!(concat!(
-                "CARGO_BIN_FILE_TEST_KERNEL_",
-                stringify!($bin_name)
-            )));
+            $crate::runner::run(
+                env!(concat!("CARGO_BIN_FILE_TEST_KERNEL_", stringify!($bin_name))),
+                &[$($arg),*],
+            );
         }
     };
 }
 
-pub fn run(path: &str) {
-    let path = Path::new(path);
-    let mut image_builder = DiskImageBuilder::new(path.to_path_buf());
+pub fn run(path: &str, args: &[&str]) {
+    // test kernels get a symbol table for backtraces like the kernel
+    let kernel = Path::new(path);
+    let path = kernel.with_extension("ksyms");
+    embed_symbol_table(kernel, &path);
+    let mut image_builder = DiskImageBuilder::new(path.clone());
     let image_path = path.with_extension(".mbr");
     image_builder
         .set_boot_config(&{
@@ -43,6 +48,7 @@ pub fn run(path: &str) {
         .arg("-display")
         .arg("none")
         .arg("--no-reboot")
+        .args(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .stdin(Stdio::null())
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::backtrace::{self, Backtrace};
+use kernel::BOOTLOADER_CONFIG;
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // the innermost frame returns into the caller of `capture`
+    let backtrace = capture();
+    let caller = backtrace.frames()[0];
+    let symbol = backtrace::resolve(caller - 1).expect("symbol table is missing");
+    assert_eq!(symbol.name, "backtrace::main");
+    assert!((symbol.address..symbol.address + symbol.size).contains(&(caller - 1)));
+
+    // the double fault handler's backtrace continues into the overflowing function
+    #[allow(unconditional_recursion)]
+    fn stack_overflow() {
+        stack_overflow();
+    }
+    stack_overflow();
+
+    exit_qemu(QemuExitCode::Failed)
+}
+
+#[inline(never)]
+fn capture() -> Backtrace {
+    Backtrace::capture()
+}
+
+/// Returns whether `address` is an instruction of `stack_overflow`, or returns into it.
+fn in_stack_overflow(address: u64) -> bool {
+    [address, address - 1]
+        .into_iter()
+        .filter_map(backtrace::resolve)
+        .any(|symbol| symbol.name.ends_with("::stack_overflow"))
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    let backtrace = Backtrace::capture();
+    let mut buf = heapless::String::<2048>::new();
+    write!(buf, "{info}").unwrap();
+    if buf.contains("stack overflow in thread main")
+        && !backtrace.frames().is_empty()
+        && backtrace
+            .frames()
+            .iter()
+            .any(|&frame| in_stack_overflow(frame))
+    {
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        writeln!(serial(), "{info}\n{backtrace}").unwrap();
+        exit_qemu(QemuExitCode::Failed);
+    }
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use kernel::{
+    interrupt::{
+        irq::{self, IrqReturn},
+        work::{self, DelayedWork, Work},
+    },
+    percpu,
+    x86_64::instructions::{hlt, interrupts},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static WORK: Work = Work::new(work);
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTS_ENABLED: AtomicBool = AtomicBool::new(false);
+static IN_INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+static REPEATED: Work = Work::new(repeated);
+static REPEATS: AtomicUsize = AtomicUsize::new(0);
+
+static DELAYED: DelayedWork = DelayedWork::new(delayed);
+static DELAYED_AT: AtomicU64 = AtomicU64::new(0);
+
+fn work() {
+    RUNS.fetch_add(1, Ordering::Relaxed);
+    INTERRUPTS_ENABLED.store(interrupts::are_enabled(), Ordering::Relaxed);
+    IN_INTERRUPT.store(percpu::in_interrupt(), Ordering::Relaxed);
+}
+
+fn repeated() {
+    if REPEATS.fetch_add(1, Ordering::Relaxed) < 2 {
+        assert!(REPEATED.schedule());
+    }
+}
+
+fn delayed() {
+    DELAYED_AT.store(work::ticks(), Ordering::Relaxed);
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // the work runs once at the tail of the interrupt, with interrupts enabled
+    let handler = irq::register_irq(5, || {
+        assert!(WORK.schedule());
+        assert!(!WORK.schedule());
+        IrqReturn::Handled
+    })
+    .unwrap();
+    unsafe { core::arch::asm!("int 0x25") };
+    assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+    assert!(INTERRUPTS_ENABLED.load(Ordering::Relaxed));
+    assert!(IN_INTERRUPT.load(Ordering::Relaxed));
+    assert!(!WORK.is_queued());
+    irq::unregister(handler);
+
+    // work scheduled outside of interrupts runs after the next one
+    assert!(REPEATED.schedule());
+    while REPEATS.load(Ordering::Relaxed) < 3 {
+        hlt();
+    }
+
+    let start = work::ticks();
+    assert!(DELAYED.schedule(50));
+    assert!(!DELAYED.schedule(10));
+    assert!(DELAYED.is_pending());
+    while DELAYED.is_pending() {
+        hlt();
+    }
+    // 50 ms are 5 ticks of the 100 Hz timer
+    assert!(DELAYED_AT.load(Ordering::Relaxed) >= start + 5);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
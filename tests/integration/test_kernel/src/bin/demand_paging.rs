@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{
+    memory::{area::Backing, MEMORY_MANAGER},
+    x86_64::{structures::paging::PageTableFlags, VirtAddr},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static FILE: [u8; 6] = *b"cosmos";
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    let memory_manager = MEMORY_MANAGER.get().unwrap();
+
+    let zero_start = VirtAddr::new(0x4343_4343_0000_u64);
+    let file_start = VirtAddr::new(0x4343_4344_0000_u64);
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            zero_start,
+            0x4000,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Zero,
+        )
+        .expect("failed to reserve zero-filled region");
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            file_start,
+            0x1000,
+            PageTableFlags::NO_EXECUTE,
+            Backing::File {
+                data: &FILE,
+                offset: 2,
+            },
+        )
+        .expect("failed to reserve file region");
+
+    // nothing is mapped until the memory is accessed
+    assert_eq!(memory_manager.lock().translate_address(zero_start), None);
+
+    let ptr = (zero_start + 0x1000u64).as_mut_ptr::<u8>();
+    unsafe { ptr.write_volatile(0x42) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 0x42);
+    assert_eq!(unsafe { ptr.add(1).read_volatile() }, 0);
+    assert_ne!(
+        memory_manager
+            .lock()
+            .translate_address(zero_start + 0x1000u64),
+        None
+    );
+    assert_eq!(memory_manager.lock().translate_address(zero_start), None);
+
+    let file = unsafe { core::slice::from_raw_parts(file_start.as_ptr::<u8>(), 6) };
+    assert_eq!(file, b"smos\0\0");
+
+    // the page after the zero-filled region does not belong to any area
+    let ptr = (zero_start + 0x4000u64).as_mut_ptr::<u8>();
+    unsafe { ptr.write_volatile(0x42) };
+
+    exit_qemu(QemuExitCode::Failed)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    let mut buf = heapless::String::<2048>::new();
+    write!(buf, "{info}").unwrap();
+    if buf.contains("segmentation violation") {
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        writeln!(serial(), "{info}").unwrap();
+        exit_qemu(QemuExitCode::Failed);
+    }
+}
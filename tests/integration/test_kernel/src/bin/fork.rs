@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{
+    memory::{area::Backing, MEMORY_MANAGER},
+    x86_64::{structures::paging::PageTableFlags, VirtAddr},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    let memory_manager = MEMORY_MANAGER.get().unwrap();
+
+    let region_start = VirtAddr::new(0x4545_4545_0000_u64);
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            region_start,
+            0x2000,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Zero,
+        )
+        .expect("failed to reserve region");
+
+    let shared = region_start.as_mut_ptr::<u64>();
+    let untouched = (region_start + 0x1000u64).as_mut_ptr::<u64>();
+    unsafe { shared.write_volatile(1) };
+
+    let child = memory_manager.lock().fork().expect("failed to fork");
+
+    // the write copies the shared page
+    unsafe { shared.write_volatile(2) };
+
+    let parent = unsafe { memory_manager.lock().switch_address_space(child) };
+    assert_eq!(unsafe { shared.read_volatile() }, 1);
+    unsafe { shared.write_volatile(3) };
+    unsafe { untouched.write_volatile(4) };
+
+    let child = unsafe { memory_manager.lock().switch_address_space(parent) };
+    assert_eq!(unsafe { shared.read_volatile() }, 2);
+    assert_eq!(unsafe { untouched.read_volatile() }, 0);
+
+    memory_manager.lock().free_address_space(child);
+
+    // freeing the child leaves the parent's copy intact
+    unsafe { shared.write_volatile(5) };
+    assert_eq!(unsafe { shared.read_volatile() }, 5);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
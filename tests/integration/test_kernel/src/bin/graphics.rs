@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::vec;
+use kernel::{
+    bootloader_api::info::{FrameBuffer, FrameBufferInfo},
+    vga::{self, bmp, Color, Rect},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+const BOOTSPLASH: &[u8] = include_bytes!("../../../../../src/assets/bootsplash.bmp");
+
+/// Returns the headers of a 32-bit uncompressed bitmap without any pixel data.
+fn header(width: i32, height: i32) -> [u8; 54] {
+    let mut header = [0; 54];
+    header[..2].copy_from_slice(b"BM");
+    header[2..6].copy_from_slice(&54u32.to_le_bytes());
+    header[10..14].copy_from_slice(&54u32.to_le_bytes());
+    header[14..18].copy_from_slice(&40u32.to_le_bytes());
+    header[18..22].copy_from_slice(&width.to_le_bytes());
+    header[22..26].copy_from_slice(&height.to_le_bytes());
+    header[26..28].copy_from_slice(&1u16.to_le_bytes());
+    header[28..30].copy_from_slice(&32u16.to_le_bytes());
+    header
+}
+
+fn decode() {
+    let image = bmp::decode(BOOTSPLASH).unwrap();
+    assert!(image.width() > 0 && image.height() > 0);
+
+    assert_eq!(
+        bmp::decode(&BOOTSPLASH[..20]),
+        Err(bmp::Error::UnexpectedEnd)
+    );
+    assert_eq!(
+        bmp::decode(&BOOTSPLASH[..BOOTSPLASH.len() - 1]),
+        Err(bmp::Error::UnexpectedEnd)
+    );
+    assert_eq!(bmp::decode(&header(1, 1)), Err(bmp::Error::UnexpectedEnd));
+    // the size of the pixel data does not fit into an `usize`
+    assert_eq!(
+        bmp::decode(&header(i32::MAX, i32::MIN + 1)),
+        Err(bmp::Error::InvalidDimensions)
+    );
+    assert_eq!(
+        bmp::decode(&header(0, 1)),
+        Err(bmp::Error::InvalidDimensions)
+    );
+}
+
+fn draw(mut info: FrameBufferInfo) {
+    info.width = 64;
+    info.height = 48;
+    info.stride = info.width;
+    info.byte_len = info.stride * info.height * info.bytes_per_pixel;
+    let mut memory = vec![0u8; info.byte_len];
+    let mut framebuffer = unsafe { FrameBuffer::new(memory.as_mut_ptr() as u64, info) };
+    let mut writer = vga::Writer::new(&mut framebuffer);
+
+    let black = Color::new(0, 0, 0);
+    let white = Color::new(0xff, 0xff, 0xff);
+    writer.fill_rect(writer.bounds(), black);
+
+    // far away endpoints are clipped instead of being walked pixel by pixel
+    writer.draw_line((isize::MIN, 5), (isize::MAX, 5), white);
+    for x in 0..writer.width() {
+        assert_eq!(writer.read_pixel(x, 5), Some(white));
+    }
+    writer.draw_line((10, isize::MIN), (10, isize::MAX), white);
+    for y in 0..writer.height() {
+        assert_eq!(writer.read_pixel(10, y), Some(white));
+    }
+    writer.draw_line((-100, -100), (1 << 40, 1 << 40), white);
+    for i in 0..writer.height() {
+        assert_eq!(writer.read_pixel(i, i), Some(white));
+    }
+    // segments completely outside of the screen do not draw anything
+    writer.draw_line((-10, -1), (isize::MAX, -1), black);
+    writer.draw_line((isize::MIN, isize::MIN), (-1, 1000), black);
+    assert_eq!(writer.read_pixel(0, 0), Some(white));
+
+    writer.fill_rect(writer.bounds(), black);
+    writer.draw_rect(Rect::new(2, 3, 4, 5), white);
+    assert_eq!(writer.read_pixel(2, 3), Some(white));
+    assert_eq!(writer.read_pixel(5, 7), Some(white));
+    assert_eq!(writer.read_pixel(3, 4), Some(black));
+
+    writer.fill_circle((0, 0), 10, white);
+    assert_eq!(writer.read_pixel(0, 0), Some(white));
+    assert_eq!(writer.read_pixel(5, 5), Some(white));
+    assert_eq!(writer.read_pixel(9, 9), Some(black));
+
+    let image = bmp::decode(BOOTSPLASH).unwrap();
+    writer.blit(&image, -(image.width() as isize) + 1, 0);
+    writer.present();
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let info = boot_info.framebuffer.as_ref().unwrap().info();
+    kernel::init(boot_info);
+
+    decode();
+    draw(info);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
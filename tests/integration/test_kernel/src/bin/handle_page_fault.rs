@@ -22,7 +22,7 @@ fn main(boot_info: &'static mut BootInfo) -> ! {
 fn panic(info: &PanicInfo) -> ! {
     use core::fmt::Write;
 
-    let mut buf = heapless::String::<512>::new();
+    let mut buf = heapless::String::<2048>::new();
     write!(buf, "{info}").unwrap();
     if buf.contains("page fault") {
         exit_qemu(QemuExitCode::Success);
@@ -23,9 +23,9 @@ fn main(boot_info: &'static mut BootInfo) -> ! {
 fn panic(info: &PanicInfo) -> ! {
     use core::fmt::Write;
 
-    let mut buf = heapless::String::<512>::new();
+    let mut buf = heapless::String::<2048>::new();
     write!(buf, "{info}").unwrap();
-    if buf.contains("double fault") {
+    if buf.contains("stack overflow in thread main") {
         exit_qemu(QemuExitCode::Success);
     } else {
         writeln!(serial(), "{info}").unwrap();
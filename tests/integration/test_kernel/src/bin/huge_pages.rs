@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{
+    memory::MEMORY_MANAGER,
+    x86_64::{structures::paging::PageTableFlags, VirtAddr},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    let memory_manager = MEMORY_MANAGER.get().unwrap();
+
+    // a 2 MiB aligned region is mapped with huge pages, i.e. physically contiguous memory
+    let region_start = VirtAddr::new(0x4646_4660_0000_u64);
+    memory_manager
+        .lock()
+        .allocate_frames_for_memory_region(
+            region_start,
+            2 * HUGE_PAGE_SIZE as usize,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        )
+        .expect("failed to allocate pages");
+
+    for page_start in [region_start, region_start + HUGE_PAGE_SIZE] {
+        let manager = memory_manager.lock();
+        let phys = manager
+            .translate_address(page_start)
+            .expect("huge page is not mapped");
+        assert!(phys.is_aligned(HUGE_PAGE_SIZE));
+        let last = manager.translate_address(page_start + (HUGE_PAGE_SIZE - 1));
+        assert_eq!(last, Some(phys + (HUGE_PAGE_SIZE - 1)));
+    }
+
+    let ptr = (region_start + HUGE_PAGE_SIZE + 0x1234u64).as_mut_ptr::<u8>();
+    unsafe { ptr.write_volatile(0x42) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 0x42);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::format;
+use kernel::{
+    interrupt::{
+        irq::{self, IrqReturn},
+        stats::{self, InterruptTable},
+        InterruptIndex,
+    },
+    x86_64::instructions::hlt,
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // every processor counts its own timer interrupts
+    let timer = InterruptIndex::LocalTimer.into();
+    while stats::count(0, timer) == 0 || stats::count(1, timer) == 0 {
+        hlt();
+    }
+    assert_eq!(
+        stats::total(timer),
+        stats::count(0, timer) + stats::count(1, timer)
+    );
+
+    let breakpoints = stats::count(0, 3);
+    kernel::x86_64::instructions::interrupts::int3();
+    assert_eq!(stats::count(0, 3), breakpoints + 1);
+
+    let handler = irq::register_irq(5, || IrqReturn::Handled).unwrap();
+    unsafe { core::arch::asm!("int 0x25") };
+    assert_eq!(stats::count(0, 0x25), 1);
+    assert_eq!(stats::count(1, 0x25), 0);
+    irq::unregister(handler);
+
+    // vectors without a handler are logged and counted
+    unsafe { core::arch::asm!("int 0xf5") };
+    assert_eq!(stats::count(0, 0xf5), 1);
+
+    let table = format!("{InterruptTable}");
+    let mut lines = table.lines();
+    let header = lines.next().unwrap();
+    assert!(header.contains("CPU0") && header.contains("CPU1"));
+    assert!(table.contains("breakpoint"));
+    assert!(table.contains("IRQ 5"));
+    assert!(table.contains("LocalTimer"));
+    assert!(table.contains("vector 0xf5"));
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
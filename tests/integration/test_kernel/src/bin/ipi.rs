@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{
+    memory::{area::Backing, MEMORY_MANAGER},
+    percpu,
+    smp::{self, CallError},
+    x86_64::{structures::paging::PageTableFlags, VirtAddr},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    let cpus = smp::cpus().len();
+    assert_eq!(cpus, 4);
+    for cpu in 0..cpus {
+        assert_eq!(smp::call_on_cpu(cpu, percpu::cpu_id), Ok(cpu));
+    }
+    assert_eq!(
+        smp::call_on_cpu(cpus, percpu::cpu_id),
+        Err(CallError::Offline)
+    );
+
+    // the function may borrow from the caller
+    let mut values = [0; 4];
+    for (cpu, value) in values.iter_mut().enumerate() {
+        smp::call_on_cpu(cpu, || *value = percpu::cpu_id() + 1).unwrap();
+    }
+    assert_eq!(values, [1, 2, 3, 4]);
+
+    let memory_manager = MEMORY_MANAGER.get().unwrap();
+    let region_start = VirtAddr::new(0x4646_4646_0000_u64);
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            region_start,
+            0x1000,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Zero,
+        )
+        .expect("failed to reserve region");
+    let shared = region_start.as_mut_ptr::<u64>();
+    let read = |cpu| {
+        let shared = shared as usize;
+        smp::call_on_cpu(cpu, move || unsafe {
+            (shared as *const u64).read_volatile()
+        })
+        .unwrap()
+    };
+
+    // every processor caches the translation of the page
+    unsafe { shared.write_volatile(1) };
+    for cpu in 0..cpus {
+        assert_eq!(read(cpu), 1);
+    }
+
+    // the write copies the page, the other processors must not read the old frame anymore
+    let child = memory_manager.lock().fork().expect("failed to fork");
+    unsafe { shared.write_volatile(2) };
+    for cpu in 0..cpus {
+        assert_eq!(read(cpu), 2);
+    }
+    memory_manager.lock().free_address_space(child);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::{
+    interrupt::{
+        apic,
+        irq::{self, IrqError, IrqReturn},
+    },
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static FIRST: AtomicUsize = AtomicUsize::new(0);
+static SECOND: AtomicUsize = AtomicUsize::new(0);
+static MSI: AtomicUsize = AtomicUsize::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    assert_eq!(
+        irq::register_irq(2, || IrqReturn::Handled),
+        Err(IrqError::InvalidLine(2))
+    );
+    assert_eq!(
+        irq::register_irq(16, || IrqReturn::Handled),
+        Err(IrqError::InvalidLine(16))
+    );
+
+    // both handlers of a shared line run
+    let first = irq::register_irq(5, || {
+        FIRST.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::NotMine
+    })
+    .unwrap();
+    let second = irq::register_irq(5, || {
+        SECOND.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    assert_eq!(first.vector(), irq::FIRST_VECTOR + 5);
+    let unhandled = irq::unhandled();
+    raise_irq_5();
+    assert_eq!(FIRST.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 1);
+    assert_eq!(irq::unhandled(), unhandled);
+
+    irq::unregister(second);
+    raise_irq_5();
+    assert_eq!(FIRST.load(Ordering::Relaxed), 2);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 1);
+    assert_eq!(irq::unhandled(), unhandled + 1);
+    irq::unregister(first);
+
+    let msi = irq::allocate_msi(|| {
+        MSI.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    let other = irq::allocate_msi(|| IrqReturn::Handled).unwrap();
+    assert_ne!(msi.vector(), other.vector());
+    assert_eq!(msi.address(), 0xfee0_0000 | u64::from(apic::id()) << 12);
+    assert_eq!(msi.data(), u16::from(msi.vector()));
+
+    // a message signaled interrupt is delivered by the local APIC like an IPI
+    apic::send_ipi(apic::id(), msi.vector());
+    while MSI.load(Ordering::Relaxed) == 0 {
+        core::hint::spin_loop();
+    }
+
+    let vector = msi.vector();
+    irq::free_msi(msi);
+    let reused = irq::allocate_msi(|| IrqReturn::Handled).unwrap();
+    assert_eq!(reused.vector(), vector);
+    irq::free_msi(reused);
+    irq::free_msi(other);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+/// Enters the stub of IRQ line 5 like the PIC would.
+fn raise_irq_5() {
+    unsafe { core::arch::asm!("int 0x25") };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::BOOTLOADER_CONFIG;
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+#[cfg(feature = "lockdep")]
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::smp;
+    use kernel::sync::{lockdep, IrqSpinLock, SpinLock};
+
+    static FIRST: SpinLock<()> = SpinLock::new(());
+    static SECOND: SpinLock<()> = SpinLock::new(());
+    static THIRD: SpinLock<()> = SpinLock::new(());
+    static SHARED: IrqSpinLock<()> = IrqSpinLock::new(());
+    // the elements share their class
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ELEMENT: SpinLock<()> = SpinLock::new(());
+    static ELEMENTS: [SpinLock<()>; 2] = [ELEMENT; 2];
+    static FOURTH: SpinLock<()> = SpinLock::new(());
+
+    kernel::init(boot_info);
+    // booting takes the kernel's locks in a valid order
+    assert_eq!(lockdep::violations(), 0);
+
+    {
+        let _first = FIRST.lock();
+        let _second = SECOND.lock();
+    }
+    {
+        let _second = SECOND.lock();
+        let _third = THIRD.lock();
+    }
+    // taking the locks in the same order again is fine
+    {
+        let _first = FIRST.lock();
+        let _second = SECOND.lock();
+    }
+    assert_eq!(lockdep::violations(), 0);
+
+    // closes the cycle FIRST -> SECOND -> THIRD -> FIRST
+    {
+        let _third = THIRD.lock();
+        let _first = FIRST.lock();
+    }
+    assert_eq!(lockdep::violations(), 1);
+    // a problem is reported only once
+    {
+        let _third = THIRD.lock();
+        let _first = FIRST.lock();
+    }
+    assert_eq!(lockdep::violations(), 1);
+
+    // trying a lock never waits, so it is no inversion
+    {
+        let _second = SECOND.lock();
+        assert!(FIRST.try_lock().is_some());
+    }
+    assert_eq!(lockdep::violations(), 1);
+
+    // the order is checked between classes, not between single locks
+    {
+        let _first_element = ELEMENTS[0].lock();
+        let _second_element = ELEMENTS[1].lock();
+        let _fourth = FOURTH.lock();
+    }
+    assert_eq!(lockdep::violations(), 1);
+    {
+        let _fourth = FOURTH.lock();
+        let _element = ELEMENTS[1].lock();
+    }
+    assert_eq!(lockdep::violations(), 2);
+
+    // the calls run in interrupt handlers
+    smp::call_on_cpu(1, || drop(SHARED.lock())).unwrap();
+    smp::call_on_cpu(1, || assert!(FIRST.try_lock().is_some())).unwrap();
+    assert_eq!(lockdep::violations(), 2);
+    smp::call_on_cpu(1, || drop(FIRST.lock())).unwrap();
+    assert_eq!(lockdep::violations(), 3);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+/// The validation is only built with the `lockdep` feature.
+#[cfg(not(feature = "lockdep"))]
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
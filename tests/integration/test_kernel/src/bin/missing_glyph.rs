@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use core::fmt::Write;
+use kernel::{
+    bootloader_api::info::{FrameBuffer, FrameBufferInfo},
+    logger::{self, Logger},
+    println, vga, BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+/// Renders `text` on a console of a few glyphs and returns the bytes of the framebuffer.
+fn render(mut info: FrameBufferInfo, text: &str) -> Vec<u8> {
+    info.width = 64;
+    info.height = 32;
+    info.stride = info.width;
+    info.byte_len = info.stride * info.height * info.bytes_per_pixel;
+    let mut memory = vec![0u8; info.byte_len];
+    let mut framebuffer = unsafe { FrameBuffer::new(memory.as_mut_ptr() as u64, info) };
+    let mut logger = Logger::new(vga::Writer::new(&mut framebuffer));
+    logger.write_str(text).unwrap();
+    logger.render();
+    drop(logger);
+    memory
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let info = boot_info.framebuffer.as_ref().unwrap().info();
+    kernel::init(boot_info);
+
+    // characters missing from the font are drawn as the replacement character
+    let replacement = render(info, "\u{FFFD}");
+    assert_ne!(replacement, render(info, " "));
+    assert_eq!(render(info, "漢"), replacement);
+    assert_eq!(render(info, "🦀"), replacement);
+
+    // the console keeps the original characters
+    println!("漢字 🦀");
+    assert_eq!(logger::recent_lines(1), ["漢字 🦀"]);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
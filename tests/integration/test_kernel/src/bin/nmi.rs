@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::{
+    interrupt::{nmi, stats},
+    x86_64::{
+        registers::control::{Cr4, Cr4Flags},
+        structures::idt::InterruptStackFrame,
+    },
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static STACK_POINTER: AtomicU64 = AtomicU64::new(0);
+
+fn watchdog(_frame: &InterruptStackFrame) -> bool {
+    let stack_pointer: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) stack_pointer) };
+    STACK_POINTER.store(stack_pointer, Ordering::Relaxed);
+    true
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // QEMU supports machine checks
+    assert!(Cr4::read().contains(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+
+    nmi::register(watchdog).unwrap();
+    let stack_pointer: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) stack_pointer) };
+    unsafe { core::arch::asm!("int 2") };
+    let nmi_stack_pointer = STACK_POINTER.load(Ordering::Relaxed);
+    assert_ne!(nmi_stack_pointer, 0);
+    // the handler runs on its own stack
+    assert!(nmi_stack_pointer.abs_diff(stack_pointer) > 64 * 1024);
+    assert_eq!(nmi::unknown(), 0);
+
+    nmi::unregister(watchdog);
+    unsafe { core::arch::asm!("int 2") };
+    assert_eq!(nmi::unknown(), 1);
+    assert_eq!(stats::count(0, 2), 2);
+
+    for _ in 0..4 {
+        nmi::register(watchdog).unwrap();
+    }
+    assert_eq!(nmi::register(watchdog), Err(nmi::NmiError::TooManyHandlers));
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
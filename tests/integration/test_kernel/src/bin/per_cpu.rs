@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::{
+    percpu::{self, PerCpu},
+    smp, BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static COUNTER: PerCpu<AtomicUsize> = PerCpu::new(|| AtomicUsize::new(0));
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    assert_eq!(percpu::cpu_id(), 0);
+    assert!(percpu::preemptible());
+
+    {
+        let guard = percpu::preempt_disable();
+        assert!(!percpu::preemptible());
+        let nested = percpu::preempt_disable();
+        drop(nested);
+        assert!(!percpu::preemptible());
+
+        COUNTER.get(&guard).fetch_add(2, Ordering::Relaxed);
+        COUNTER.get(&guard).fetch_add(1, Ordering::Relaxed);
+    }
+    assert!(percpu::preemptible());
+
+    // only the bootstrap processor has accessed its value
+    assert_eq!(
+        COUNTER
+            .get_for(0)
+            .map(|value| value.load(Ordering::Relaxed)),
+        Some(3)
+    );
+    assert!(COUNTER.get_for(1).is_none());
+    assert_eq!(COUNTER.iter().count(), 1);
+
+    // the application processor has its own area and values
+    assert_eq!(smp::call_on_cpu(1, percpu::cpu_id), Ok(1));
+    let value = smp::call_on_cpu(1, || {
+        let guard = percpu::preempt_disable();
+        COUNTER.get(&guard).fetch_add(5, Ordering::Relaxed);
+        COUNTER.get(&guard).load(Ordering::Relaxed)
+    });
+    assert_eq!(value, Ok(5));
+    assert_eq!(
+        COUNTER
+            .get_for(1)
+            .map(|value| value.load(Ordering::Relaxed)),
+        Some(5)
+    );
+    assert_eq!(
+        COUNTER
+            .get_for(0)
+            .map(|value| value.load(Ordering::Relaxed)),
+        Some(3)
+    );
+    assert_eq!(COUNTER.iter().count(), 2);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::vec;
+use kernel::{
+    bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat},
+    vga::{self, Color},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+/// Draws a pixel of `color` into a framebuffer of a single pixel and returns its bytes.
+fn encode(
+    mut info: FrameBufferInfo,
+    format: PixelFormat,
+    bytes_per_pixel: usize,
+    color: Color,
+) -> [u8; 4] {
+    info.pixel_format = format;
+    info.bytes_per_pixel = bytes_per_pixel;
+    info.width = 1;
+    info.height = 1;
+    info.stride = 1;
+    info.byte_len = bytes_per_pixel;
+    let mut memory = vec![0u8; bytes_per_pixel];
+    let mut framebuffer = unsafe { FrameBuffer::new(memory.as_mut_ptr() as u64, info) };
+    let mut writer = vga::Writer::new(&mut framebuffer);
+    writer.write_pixel(0, 0, color);
+    writer.present();
+    drop(writer);
+
+    let mut bytes = [0; 4];
+    bytes[..bytes_per_pixel].copy_from_slice(&memory);
+    bytes
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let info = boot_info.framebuffer.as_ref().unwrap().info();
+    kernel::init(boot_info);
+
+    let red = Color::new(0xff, 0, 0);
+    let blue = Color::new(0, 0, 0xff);
+
+    // xRGB, the red channel must not spill into the padding byte
+    let xrgb = PixelFormat::Unknown {
+        red_position: 16,
+        green_position: 8,
+        blue_position: 0,
+    };
+    assert_eq!(encode(info, xrgb, 4, red), [0, 0, 0xff, 0]);
+    assert_eq!(encode(info, xrgb, 4, blue), [0xff, 0, 0, 0]);
+
+    // xRGB 1555
+    let rgb555 = PixelFormat::Unknown {
+        red_position: 10,
+        green_position: 5,
+        blue_position: 0,
+    };
+    assert_eq!(encode(info, rgb555, 2, red), [0x00, 0x7c, 0, 0]);
+
+    // RGB 565
+    let rgb565 = PixelFormat::Unknown {
+        red_position: 11,
+        green_position: 5,
+        blue_position: 0,
+    };
+    assert_eq!(encode(info, rgb565, 2, red), [0x00, 0xf8, 0, 0]);
+    assert_eq!(
+        encode(info, rgb565, 2, Color::new(0, 0xff, 0)),
+        [0xe0, 0x07, 0, 0]
+    );
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
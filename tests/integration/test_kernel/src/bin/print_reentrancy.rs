@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::fmt;
+use kernel::{logger, println, BOOTLOADER_CONFIG};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+/// Prints while it is being printed, like an interrupt handler that prints while the console is
+/// locked.
+struct Reentrant;
+
+impl fmt::Display for Reentrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        println!("inner");
+        write!(f, "outer")
+    }
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    println!("{}", Reentrant);
+    // the nested output is queued and written right after the outer one
+    assert_eq!(logger::recent_lines(2), ["outer", "inner"]);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use kernel::{logger, percpu, println, BOOTLOADER_CONFIG};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    // printing needs the per-CPU data, but neither the heap nor the console
+    percpu::init(0);
+    println!("before the console");
+    kernel::init(boot_info);
+    // the early output is the first line of the console
+    let history = logger::recent_lines(usize::MAX);
+    assert_eq!(
+        history.first().map(String::as_str),
+        Some("before the console")
+    );
+
+    let rows = logger::rows();
+    assert!(rows > 0);
+    let lines: Vec<String> = (0..2 * rows).map(|line| format!("line {line}")).collect();
+    for line in &lines {
+        println!("{line}");
+    }
+    assert_eq!(logger::recent_lines(2 * rows), lines);
+
+    // the cursor is on the empty line below the output
+    let mut screen = lines.clone();
+    screen.push(String::new());
+    let bottom = screen.len();
+    assert_eq!(logger::visible_lines(), screen[bottom - rows..]);
+
+    logger::page_up();
+    assert_eq!(
+        logger::visible_lines(),
+        screen[bottom - 2 * rows..bottom - rows]
+    );
+    // scrolling only moves the view, the history stays the same
+    assert_eq!(logger::recent_lines(2 * rows), lines);
+
+    logger::page_down();
+    assert_eq!(logger::visible_lines(), screen[bottom - rows..]);
+
+    // new output brings the view back to the bottom
+    logger::page_up();
+    println!("last line");
+    assert!(logger::visible_lines().ends_with(&[String::from("last line"), String::new()]));
+    assert_eq!(logger::recent_lines(1), ["last line"]);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use kernel::{
+    allocator::{
+        reserve,
+        slab::{self, PageSource, SlabCache},
+        HEAP_SIZE, HEAP_START,
+    },
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+type Object = [u64; 4];
+
+fn construct(object: NonNull<u8>) {
+    unsafe { object.cast::<Object>().as_ptr().write([0x42; 4]) };
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    let mut cache = SlabCache::new(
+        "test-object",
+        Layout::new::<Object>(),
+        Some(construct),
+        PageSource::Frames,
+    );
+
+    let objects: Vec<_> = (0..200)
+        .map(|_| cache.allocate().expect("failed to allocate object"))
+        .collect();
+    for object in &objects {
+        assert_eq!(object.as_ptr() as usize % 8, 0);
+        assert_eq!(
+            unsafe { object.cast::<Object>().as_ptr().read() },
+            [0x42; 4]
+        );
+    }
+
+    let stats = cache.stats();
+    assert_eq!(stats.objects_in_use, 200);
+    assert_eq!(stats.slabs, 2);
+    assert!(stats.capacity >= 200);
+
+    // nothing can be reclaimed while the slabs are in use
+    assert_eq!(cache.reclaim(), 0);
+    for object in objects {
+        unsafe { cache.deallocate(object) };
+    }
+    assert_eq!(cache.reclaim(), 2);
+
+    let stats = cache.stats();
+    assert_eq!((stats.slabs, stats.objects_in_use), (0, 0));
+    assert_eq!(
+        (stats.allocations, stats.frees, stats.reclaimed_slabs),
+        (200, 200, 2)
+    );
+
+    // small allocations of the global allocator are served by the size classes
+    let boxed = Box::new([0u8; 24]);
+    let stats = slab::stats();
+    let class = stats
+        .iter()
+        .find(|stats| stats.name == "kmalloc-32")
+        .expect("size class is not registered");
+    assert!(class.objects_in_use > 0);
+    drop(boxed);
+
+    // their slabs are frames of the reserve, which is refilled outside of the heap
+    let heap = HEAP_START..HEAP_START + HEAP_SIZE as u64;
+    let boxes: Vec<_> = (0..64).map(|_| Box::new([0u8; 512])).collect();
+    assert!(boxes
+        .iter()
+        .any(|boxed| !heap.contains(&(boxed.as_ptr() as u64))));
+    assert!(reserve::len() > 0);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{smp, BOOTLOADER_CONFIG};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // the test runs with 4 processors, all of which check in during initialization
+    let cpus = smp::cpus();
+    assert_eq!(cpus.len(), 4);
+    for (index, cpu) in cpus.iter().enumerate() {
+        assert_eq!(cpu.index(), index);
+        assert!(cpu.is_online(), "CPU {index} did not check in");
+    }
+    let mut apic_ids = [0; 4];
+    for (id, cpu) in apic_ids.iter_mut().zip(cpus) {
+        *id = cpu.apic_id();
+    }
+    apic_ids.sort_unstable();
+    assert!(apic_ids.windows(2).all(|ids| ids[0] != ids[1]));
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
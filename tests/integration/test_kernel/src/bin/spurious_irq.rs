@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::{
+    interrupt::{
+        irq::{self, IrqReturn},
+        stats,
+    },
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // software interrupts are never in service at the PICs, so they look spurious
+    let handler = irq::register_irq(7, || {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    let spurious = irq::spurious();
+    unsafe { core::arch::asm!("int 0x27") };
+    assert_eq!(irq::spurious(), spurious + 1);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 0);
+    assert_eq!(stats::count(0, 0x27), 1);
+    irq::unregister(handler);
+
+    unsafe { core::arch::asm!("int 0x2f") };
+    assert_eq!(irq::spurious(), spurious + 2);
+
+    // the other lines are dispatched as usual
+    let handler = irq::register_irq(5, || {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    unsafe { core::arch::asm!("int 0x25") };
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    irq::unregister(handler);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
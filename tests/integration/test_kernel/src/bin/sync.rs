@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{
+    interrupt::work::DelayedWork,
+    smp,
+    sync::{Condvar, IrqSpinLock, Mutex, RwLock, Semaphore},
+    x86_64::instructions::interrupts,
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static COUNTER: Mutex<u64> = Mutex::new(0);
+static IRQ_COUNTER: IrqSpinLock<u64> = IrqSpinLock::new(0);
+
+static HELD: Semaphore = Semaphore::new(1);
+static RELEASE_HELD: DelayedWork = DelayedWork::new(release_held);
+static HANDOFF: Semaphore = Semaphore::new(0);
+static RELEASE_ON_CPU_1: DelayedWork = DelayedWork::new(release_on_cpu_1);
+
+fn release_held() {
+    HELD.release();
+}
+
+fn release_on_cpu_1() {
+    smp::call_on_cpu(1, || HANDOFF.release()).unwrap();
+}
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // the interrupt flag is restored, also when the lock is nested
+    let lock = IrqSpinLock::new(1);
+    assert!(interrupts::are_enabled());
+    {
+        let mut guard = lock.lock();
+        assert!(!interrupts::are_enabled());
+        *guard += 1;
+        assert!(lock.try_lock().is_none());
+        assert!(!interrupts::are_enabled());
+
+        let nested = IrqSpinLock::new(());
+        drop(nested.lock());
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 2);
+
+    {
+        let guard = COUNTER.lock();
+        assert!(COUNTER.try_lock().is_none());
+        drop(guard);
+    }
+    *COUNTER.lock() += 1;
+    assert_eq!(*COUNTER.lock(), 1);
+
+    // the processors take the lock in turn, the calls run in interrupt handlers
+    for cpu in 0..smp::cpus().len() {
+        smp::call_on_cpu(cpu, || *IRQ_COUNTER.lock() += 1).unwrap();
+    }
+    assert_eq!(*IRQ_COUNTER.lock(), 2);
+
+    let rwlock = RwLock::new(3);
+    {
+        let first = rwlock.read();
+        let second = rwlock.try_read().expect("readers share the lock");
+        assert_eq!(*first + *second, 6);
+        assert!(rwlock.try_write().is_none());
+    }
+    {
+        let mut writer = rwlock.write();
+        *writer = 4;
+        assert!(rwlock.try_read().is_none());
+        assert!(rwlock.try_write().is_none());
+    }
+    assert_eq!(*rwlock.read(), 4);
+
+    let semaphore = Semaphore::new(2);
+    semaphore.acquire();
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+    semaphore.release();
+    semaphore.acquire();
+    assert_eq!(semaphore.available_permits(), 0);
+
+    // the processor 1 waits for the permit held by this processor until its timer returns it
+    HELD.acquire();
+    assert!(RELEASE_HELD.schedule(10));
+    let waited = smp::call_on_cpu(1, || {
+        let contended = !HELD.try_acquire();
+        if contended {
+            HELD.acquire();
+        }
+        contended
+    });
+    assert_eq!(waited, Ok(true));
+    HELD.release();
+    assert_eq!(HELD.available_permits(), 1);
+
+    // this processor halts until the processor 1 returns the permit and wakes it
+    assert!(RELEASE_ON_CPU_1.schedule(10));
+    assert!(!HANDOFF.try_acquire());
+    HANDOFF.acquire();
+    assert!(interrupts::are_enabled());
+    assert_eq!(HANDOFF.available_permits(), 0);
+
+    let ready = Mutex::new(true);
+    let condvar = Condvar::new();
+    condvar.notify_all();
+    let guard = condvar.wait_while(ready.lock(), |ready| !*ready);
+    assert!(*guard);
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use kernel::{
+    allocator::HEAP_START,
+    memory::{
+        area::Backing,
+        user::{copy_from_user, copy_to_user, Efault, UserPtr},
+        MEMORY_MANAGER,
+    },
+    x86_64::{structures::paging::PageTableFlags, VirtAddr},
+    BOOTLOADER_CONFIG,
+};
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    let memory_manager = MEMORY_MANAGER.get().unwrap();
+
+    let user_start = VirtAddr::new(0x4747_4747_0000_u64);
+    let read_only_start = VirtAddr::new(0x4747_4748_0000_u64);
+    let stale_start = VirtAddr::new(0x4747_4749_0000_u64);
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            user_start,
+            0x2000,
+            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Zero,
+        )
+        .expect("failed to reserve user region");
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            read_only_start,
+            0x1000,
+            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE,
+            Backing::Zero,
+        )
+        .expect("failed to reserve read-only user region");
+
+    // copies across a page boundary, mapping the pages on demand
+    let user = UserPtr::<u8>::new(user_start + 0xffcu64);
+    copy_to_user(user, b"nebula").unwrap();
+    let mut buf = [0; 6];
+    copy_from_user(&mut buf, user).unwrap();
+    assert_eq!(&buf, b"nebula");
+
+    let value = UserPtr::<u64>::new(user_start).offset(2);
+    value.write(0xdead_beef_cafe_f00d).unwrap();
+    assert_eq!(value.read(), Ok(0xdead_beef_cafe_f00d));
+
+    // ranges outside the user areas are rejected
+    let mut buf = [0; 16];
+    let kernel = UserPtr::new(VirtAddr::new(HEAP_START));
+    assert_eq!(copy_from_user(&mut buf, kernel), Err(Efault));
+    let overlapping = UserPtr::new(user_start + 0x1ff8u64);
+    assert_eq!(copy_from_user(&mut buf, overlapping), Err(Efault));
+    let read_only = UserPtr::new(read_only_start);
+    assert_eq!(copy_to_user(read_only, &buf), Err(Efault));
+    assert_eq!(copy_from_user(&mut buf, read_only), Ok(()));
+
+    // a page whose mapping does not permit the access its area permits faults inside the copy
+    // routine, which must be reported instead of panicking
+    memory_manager
+        .lock()
+        .allocate_frames_for_memory_region(
+            stale_start,
+            0x1000,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE,
+        )
+        .expect("failed to map stale region");
+    memory_manager
+        .lock()
+        .reserve_memory_region(
+            stale_start,
+            0x1000,
+            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Zero,
+        )
+        .expect("failed to reserve stale region");
+    let stale = UserPtr::new(stale_start);
+    assert_eq!(copy_to_user(stale, b"nebula"), Err(Efault));
+    assert_eq!(copy_from_user(&mut buf, stale), Ok(()));
+
+    exit_qemu(QemuExitCode::Success)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    writeln!(serial(), "{info}").unwrap();
+    exit_qemu(QemuExitCode::Failed);
+}
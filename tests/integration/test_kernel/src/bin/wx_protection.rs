@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU8, Ordering};
+use kernel::BOOTLOADER_CONFIG;
+use test_kernel::prelude::*;
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+/// Number of expected faults which occurred so far.
+static STAGE: AtomicU8 = AtomicU8::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // the code of the kernel is not writable
+    let code = main as *mut u8;
+    unsafe { code.write_volatile(0xC3) };
+
+    exit_qemu(QemuExitCode::Failed)
+}
+
+/// Executes a `ret` instruction on the heap.
+fn execute_from_heap() {
+    let code = Box::new([0xC3_u8]);
+    let function: extern "C" fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    function();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    let mut buf = heapless::String::<2048>::new();
+    write!(buf, "{info}").unwrap();
+    let expected = match STAGE.load(Ordering::Relaxed) {
+        0 => "kernel-mode write to a present page",
+        _ => "kernel-mode instruction fetch from a present page",
+    };
+    if !buf.contains(expected) {
+        writeln!(serial(), "{info}").unwrap();
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    if STAGE.fetch_add(1, Ordering::Relaxed) == 0 {
+        execute_from_heap();
+        exit_qemu(QemuExitCode::Failed);
+    }
+    exit_qemu(QemuExitCode::Success)
+}